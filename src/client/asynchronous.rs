@@ -0,0 +1,286 @@
+use std::{fmt::Display, future::Future, time::Instant};
+
+use super::{budget, ClientConfig, Session};
+use crate::tokenizer::Tokenizer;
+use crate::transcript::Metadata;
+use crate::tree::ConversationTree;
+use crate::{AsyncProvider, Completion, Message, ProviderError, Request};
+
+/// The asynchronous counterpart of `Client`, maintaining a conversation's
+/// context and applying the same token budget and retry policy. Available
+/// with the `async` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use air::client::AsyncClient;
+/// use air::Message;
+/// use air::host::OpenAI;
+///
+/// # async fn run() {
+/// let model = OpenAI::new("gpt-3.5-turbo", "my-api-key");
+/// let mut client = AsyncClient::new(model);
+/// let message = Message::user("What is the meaning of life?");
+/// let answer = client.send(message).await;
+/// # }
+/// ```
+pub struct AsyncClient {
+    session: Session,
+    provider: Box<dyn AsyncProvider>,
+    http_client: reqwest::Client,
+}
+
+impl Display for AsyncClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.provider)
+    }
+}
+
+impl AsyncClient {
+    /// Create a new client with the given model provider
+    pub fn new<P: AsyncProvider + 'static>(provider: P) -> Self {
+        Self {
+            session: Session::new(provider.encoding()),
+            provider: Box::new(provider),
+            http_client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_context(mut self, context: Vec<Message>) -> Self {
+        self.session
+            .set_history(ConversationTree::from_messages(context));
+        self
+    }
+
+    /// The messages sent with the next request, less any dropped or
+    /// summarized to fit the token budget.
+    pub fn context(&self) -> Vec<Message> {
+        self.session.context()
+    }
+
+    /// Every message exchanged, including those left out of the context.
+    pub fn history(&self) -> &ConversationTree {
+        self.session.history()
+    }
+
+    pub fn with(mut self, config: ClientConfig) -> Self {
+        self.session.config = config;
+        self
+    }
+
+    /// Count tokens with the given tokenizer rather than the one resolved
+    /// from the provider's advertised encoding.
+    pub fn with_tokenizer<T: Tokenizer + 'static>(self, tokenizer: T) -> Self {
+        self.session.set_tokenizer(Box::new(tokenizer));
+        self
+    }
+
+    /// The tokenizer for the provider's model, resolved on first use.
+    pub fn tokenizer(&self) -> &dyn Tokenizer {
+        self.session.tokenizer()
+    }
+
+    /// Why the tokenizer for the provider's encoding could not be loaded from
+    /// `tokenizer_dir`, if it could not; token counts are then estimated.
+    pub fn tokenizer_error(&self) -> Option<&anyhow::Error> {
+        self.session.tokenizer_error()
+    }
+
+    /// Number of tokens in the current context, as counted locally.
+    pub fn count_tokens(&self) -> usize {
+        self.session.count_tokens()
+    }
+
+    /// Tokens sent to the provider so far, as it reported them, or `None` if
+    /// any response omitted the count.
+    pub fn tokens_sent(&self) -> Option<u64> {
+        self.session.tokens_sent()
+    }
+
+    /// Metadata describing the most recent response.
+    pub fn last_metadata(&self) -> Option<&Metadata> {
+        self.session.last_metadata()
+    }
+
+    /// Names of the models the provider offers.
+    pub async fn models(&self) -> Result<Vec<String>, ProviderError> {
+        self.provider.models(&self.http_client).await
+    }
+
+    /// Start the conversation afresh on a new branch of the history, keeping
+    /// its opening system messages if the config sets `retain_system`.
+    pub fn clear(&mut self) {
+        self.session.clear();
+    }
+
+    /// Send a message to the model alongside the existing context
+    pub async fn send(&mut self, content: Message) -> Result<&Message, ProviderError> {
+        let checkpoint = self.session.checkpoint();
+        self.session.remember(content);
+        if let Err(err) = self.enforce_budget().await {
+            self.session.restore(checkpoint);
+            return Err(err);
+        }
+
+        let started = Instant::now();
+        let context = self.session.context();
+        let request = Request::new(&context).with_parameters(&self.session.config.parameters);
+        let Completion {
+            message,
+            usage,
+            finish_reason,
+        } = self
+            .retrying(|| self.provider.send(&request, &self.http_client))
+            .await?;
+
+        self.session
+            .answer(message, &self.provider, started, usage, finish_reason);
+
+        Ok(self.session.last())
+    }
+
+    /// Apply the configured `BudgetPolicy` if the context's token count
+    /// exceeds `max_tokens`.
+    async fn enforce_budget(&mut self) -> Result<(), ProviderError> {
+        let Some(overflow) = self.session.overflow()? else {
+            return Ok(());
+        };
+        let summary = match overflow.summary_request() {
+            Some(request) => Some(self.summarize(&request).await?),
+            None => None,
+        };
+        self.session.compact(overflow, summary)
+    }
+
+    /// Ask the provider for the summary `request` describes, as a system
+    /// message standing in for the messages summarized.
+    async fn summarize(&mut self, request: &[Message]) -> Result<Message, ProviderError> {
+        let request = Request::new(request);
+        let summary = self
+            .retrying(|| self.provider.send(&request, &self.http_client))
+            .await?;
+        self.session.track(&summary.usage);
+
+        Ok(budget::summary_message(&summary.message.content))
+    }
+
+    /// Await `request`, retrying transient failures per the configured
+    /// `RetryPolicy`.
+    async fn retrying<T, F, Fut>(&self, request: F) -> Result<T, ProviderError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, ProviderError>>,
+    {
+        let mut retry = 0;
+        loop {
+            match request().await {
+                Ok(value) => return Ok(value),
+                Err(err) => match self.session.retry_delay(retry, &err) {
+                    None => return Err(err),
+                    Some(delay) => {
+                        tokio::time::sleep(delay).await;
+                        retry += 1;
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::RetryPolicy;
+    use crate::host::{Custom, OpenAI};
+    use crate::test_utils::{MockResponse, MockServer};
+    use std::str::FromStr;
+    use std::time::Duration;
+    use url::Url;
+
+    #[tokio::test]
+    async fn test_async_client_send() {
+        let server = MockServer::start(vec![MockResponse::new(200, "Hello, user!")]);
+        let provider = Custom::new(Url::from_str(server.url()).unwrap());
+        let mut client = AsyncClient::new(provider);
+
+        let response = client.send(Message::user("Hello, assistant!")).await;
+        assert_eq!(response.unwrap(), &Message::assistant("Hello, user!"));
+        assert_eq!(client.context().len(), 2);
+        assert_eq!(
+            server.requests()[0].json(),
+            serde_json::json!([{"role": "user", "content": "Hello, assistant!"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_client_retries() {
+        let body = serde_json::json!({
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hello, user!"},
+                "finish_reason": "stop",
+                "logprobs": null
+            }],
+            "usage": {"prompt_tokens": 9, "completion_tokens": 4, "total_tokens": 13}
+        });
+        let server = MockServer::start(vec![
+            MockResponse::new(502, "bad gateway"),
+            MockResponse::json(200, body),
+        ]);
+        let config = ClientConfig {
+            retry: RetryPolicy {
+                base_delay: Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let provider = OpenAI::new("gpt-4", "key").with_base_url(server.url());
+        let mut client = AsyncClient::new(provider).with(config);
+
+        let response = client.send(Message::user("Hello, assistant!")).await;
+        assert_eq!(response.unwrap(), &Message::assistant("Hello, user!"));
+        assert_eq!(client.tokens_sent(), Some(13));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_async_client_budget_refuse() {
+        let server = MockServer::start(vec![]);
+        let provider = Custom::new(Url::from_str(server.url()).unwrap());
+        let config = ClientConfig {
+            max_tokens: Some(3),
+            ..Default::default()
+        };
+        let mut client = AsyncClient::new(provider).with(config);
+
+        let response = client.send(Message::user("Hello, assistant!")).await;
+        assert!(matches!(
+            response,
+            Err(ProviderError::BudgetExceeded { .. })
+        ));
+        assert!(client.history().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_async_client_models() {
+        let body = serde_json::json!({"data": [{"id": "small"}, {"id": "large"}]});
+        let server = MockServer::start(vec![MockResponse::json(200, body)]);
+        let spec = serde_json::from_value(serde_json::json!({
+            "body": {"messages": "{{messages}}"},
+            "content": "/reply",
+            "models": {"url": "/v1/models", "list": "/data", "name": "/id"}
+        }))
+        .unwrap();
+        let url = Url::from_str(server.url()).unwrap();
+        let client = AsyncClient::new(Custom::new(url.clone()).with_spec(spec));
+        assert_eq!(client.models().await.unwrap(), vec!["small", "large"]);
+        assert_eq!(server.requests()[0].path, "/v1/models");
+
+        // a host whose spec does not say where its models are cannot list them
+        let client = AsyncClient::new(Custom::new(url));
+        assert!(matches!(
+            client.models().await,
+            Err(ProviderError::Unsupported(_))
+        ));
+    }
+}
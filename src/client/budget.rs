@@ -0,0 +1,215 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+};
+
+use crate::tree::{ConversationTree, NodeId};
+use crate::{Message, Role};
+
+/// What a `Client` does when the context would exceed `ClientConfig::max_tokens`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BudgetPolicy {
+    /// Send nothing and return `ProviderError::BudgetExceeded`
+    #[default]
+    Refuse,
+
+    /// Drop the oldest non-system messages until the context fits
+    Truncate,
+
+    /// Replace the oldest non-system messages with a model-written summary
+    Summarize,
+}
+
+impl FromStr for BudgetPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "refuse" => Ok(BudgetPolicy::Refuse),
+            "truncate" => Ok(BudgetPolicy::Truncate),
+            "summarize" => Ok(BudgetPolicy::Summarize),
+            _ => Err(format!("unknown budget policy: {s}")),
+        }
+    }
+}
+
+impl Display for BudgetPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BudgetPolicy::Refuse => write!(f, "refuse"),
+            BudgetPolicy::Truncate => write!(f, "truncate"),
+            BudgetPolicy::Summarize => write!(f, "summarize"),
+        }
+    }
+}
+
+/// Indices of the oldest messages to drop so that `context` fits within
+/// `limit` tokens. System messages and the final message are never dropped;
+/// returns `None` if the context cannot fit even with every other message gone.
+pub(crate) fn overflow(
+    context: &[Message],
+    limit: usize,
+    count: impl Fn(&[Message]) -> usize,
+) -> Option<Vec<usize>> {
+    let last = context.len().saturating_sub(1);
+    let mut total = count(context);
+    let mut dropped = Vec::new();
+    for (i, message) in context.iter().enumerate().take(last) {
+        if total <= limit {
+            break;
+        }
+        if message.role != Role::System {
+            total -= count(std::slice::from_ref(message));
+            dropped.push(i);
+        }
+    }
+    (total <= limit).then_some(dropped)
+}
+
+/// A request asking the model to condense the messages at `indices`.
+pub(crate) fn summary_request(context: &[Message], indices: &[usize]) -> [Message; 2] {
+    const PROMPT: &str = "Summarize the following conversation in a few sentences, \
+        keeping any facts, decisions and open questions needed to continue it.";
+
+    let conversation = indices
+        .iter()
+        .map(|&i| format!("{}: {}", context[i].role, context[i].content))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    [Message::system(PROMPT), Message::user(conversation)]
+}
+
+/// The system message standing in for summarized messages.
+pub(crate) fn summary_message(summary: &str) -> Message {
+    Message::system(format!("Summary of the earlier conversation: {summary}"))
+}
+
+/// Messages of a conversation's history left out of its context to keep it
+/// within budget, and the summaries sent in their place.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Compaction {
+    dropped: HashSet<NodeId>,
+
+    /// Summaries, each sent in place of the dropped node it is keyed by
+    summaries: HashMap<NodeId, Message>,
+}
+
+impl Compaction {
+    /// The context for the active branch of `history`.
+    pub(crate) fn context(&self, history: &ConversationTree) -> Vec<Message> {
+        self.entries(history)
+            .map(|(_, message)| message.clone())
+            .collect()
+    }
+
+    /// Leave the messages at `indices` of the context for `history` out of
+    /// it, with `summary` (if any) in place of the first of them.
+    pub(crate) fn exclude(
+        &mut self,
+        history: &ConversationTree,
+        indices: &[usize],
+        summary: Option<Message>,
+    ) {
+        let sources: Vec<_> = self.entries(history).map(|(id, _)| id).collect();
+        let dropped: Vec<_> = indices.iter().filter_map(|&i| sources[i]).collect();
+        if let (Some(summary), Some(&first)) = (summary, dropped.first()) {
+            self.summaries.insert(first, summary);
+        }
+        self.dropped.extend(dropped);
+    }
+
+    /// Send the message at `id` again, e.g. a prompt whose reply is being
+    /// regenerated.
+    pub(crate) fn keep(&mut self, id: NodeId) {
+        self.dropped.remove(&id);
+    }
+
+    /// Treat `copy` as `original` was, e.g. when a branch is copied below a
+    /// new system message.
+    pub(crate) fn carry(&mut self, original: NodeId, copy: NodeId) {
+        if self.dropped.contains(&original) {
+            self.dropped.insert(copy);
+        }
+        if let Some(summary) = self.summaries.get(&original).cloned() {
+            self.summaries.insert(copy, summary);
+        }
+    }
+
+    /// Each message of the context for `history` with the node it was taken
+    /// from, if it is not a summary.
+    fn entries<'a>(
+        &'a self,
+        history: &'a ConversationTree,
+    ) -> impl Iterator<Item = (Option<NodeId>, &'a Message)> + 'a {
+        history.path().into_iter().flat_map(move |id| {
+            let summary = self.summaries.get(&id).map(|summary| (None, summary));
+            let message = (!self.dropped.contains(&id))
+                .then(|| (Some(id), &history.get(id).unwrap().message));
+            summary.into_iter().chain(message)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::{CharHeuristic, Tokenizer};
+
+    #[test]
+    fn test_budget_policy_round_trip() {
+        for policy in [
+            BudgetPolicy::Refuse,
+            BudgetPolicy::Truncate,
+            BudgetPolicy::Summarize,
+        ] {
+            assert_eq!(policy.to_string().parse::<BudgetPolicy>(), Ok(policy));
+        }
+        assert!("spend".parse::<BudgetPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_overflow_keeps_system_and_last() {
+        let context = vec![
+            Message::system("s"),
+            Message::user("a"),
+            Message::assistant("b"),
+            Message::user("c"),
+        ];
+        // each single-character message estimates to 5 tokens
+        let estimate = |messages: &[Message]| CharHeuristic.count_messages(messages);
+        assert_eq!(overflow(&context, 20, estimate), Some(vec![]));
+        assert_eq!(overflow(&context, 15, estimate), Some(vec![1]));
+        assert_eq!(overflow(&context, 10, estimate), Some(vec![1, 2]));
+        assert_eq!(overflow(&context, 9, estimate), None);
+    }
+
+    #[test]
+    fn test_compaction_follows_history() {
+        let mut history = ConversationTree::from_messages([
+            Message::system("s"),
+            Message::user("a"),
+            Message::assistant("b"),
+        ]);
+        let mut compaction = Compaction::default();
+        compaction.exclude(&history, &[1, 2], Some(Message::system("sum")));
+        history.push(Message::user("c"));
+        assert_eq!(
+            compaction.context(&history),
+            vec![
+                Message::system("s"),
+                Message::system("sum"),
+                Message::user("c")
+            ]
+        );
+
+        // a branch that left before the dropped messages keeps its own
+        let root = history.path()[0];
+        history.fork(root);
+        history.push(Message::user("d"));
+        assert_eq!(
+            compaction.context(&history),
+            vec![Message::system("s"), Message::user("d")]
+        );
+    }
+}
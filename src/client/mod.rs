@@ -1,11 +1,52 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display, path::PathBuf, thread::sleep, time::Instant};
 
-use crate::{Message, Provider, ProviderError};
+use crate::tokenizer::Tokenizer;
+use crate::tool::{Tool, ToolCall};
+use crate::transcript::Metadata;
+use crate::tree::ConversationTree;
+use crate::{host::Usage, Completion, Message, Parameters, Provider, ProviderError, Request};
+
+#[cfg(feature = "async")]
+mod asynchronous;
+mod budget;
+mod retry;
+mod session;
+#[cfg(feature = "async")]
+pub use asynchronous::AsyncClient;
+pub use budget::BudgetPolicy;
+pub use retry::RetryPolicy;
+use session::Session;
+
+/// Rounds of tool calls answered for a single message before giving up on
+/// the model reaching a final answer.
+const MAX_TOOL_ROUNDS: usize = 8;
+
+/// A Rust function answering calls to a `Tool`, given the parsed arguments.
+/// Errors are reported back to the model as the call's result.
+type ToolHandler = Box<dyn Fn(serde_json::Value) -> anyhow::Result<String>>;
 
 #[derive(Default)]
 pub struct ClientConfig {
     pub model_name: Option<String>,
+
+    /// Maximum context size in tokens, as counted locally, to send with each request
     pub max_tokens: Option<usize>,
+
+    /// How to bring the context back within `max_tokens` when it overflows
+    pub budget_policy: BudgetPolicy,
+
+    /// Directory holding tiktoken rank files (e.g. `cl100k_base.tiktoken`)
+    /// for exact token counts; a character heuristic is used otherwise
+    pub tokenizer_dir: Option<PathBuf>,
+
+    /// Backoff applied to transient provider errors
+    pub retry: RetryPolicy,
+
+    /// Generation settings sent with every request
+    pub parameters: Parameters,
+
+    /// Keep system messages when the context is cleared
+    pub retain_system: bool,
     pub verbose: bool,
 }
 
@@ -26,11 +67,11 @@ pub struct ClientConfig {
 /// let answer = client.send(message);
 /// ```
 pub struct Client {
-    pub context: Vec<Message>,
-    pub tokens_sent: Option<u64>,
+    session: Session,
     provider: Box<dyn Provider>,
-    config: ClientConfig,
     http_client: reqwest::blocking::Client,
+    tools: Vec<Tool>,
+    handlers: HashMap<String, ToolHandler>,
 }
 
 impl Display for Client {
@@ -43,74 +84,761 @@ impl Client {
     /// Create a new client with the given model provider
     pub fn new<P: Provider + 'static>(provider: P) -> Self {
         Self {
-            tokens_sent: Some(0),
-            context: Vec::new(),
+            session: Session::new(provider.encoding()),
             provider: Box::new(provider),
-            config: ClientConfig::default(),
             http_client: reqwest::blocking::Client::new(),
+            tools: Vec::new(),
+            handlers: HashMap::new(),
         }
     }
 
     pub fn config(mut self, config: ClientConfig) -> Self {
-        self.config = config;
+        self.session.config = config;
+        self
+    }
+
+    pub fn with_context(self, context: Vec<Message>) -> Self {
+        self.with_history(ConversationTree::from_messages(context))
+    }
+
+    /// Continue the active branch of a saved conversation, keeping the others.
+    pub fn with_history(mut self, history: ConversationTree) -> Self {
+        self.set_history(history);
         self
     }
 
-    pub fn with_context(mut self, context: Vec<Message>) -> Self {
-        self.context = context;
+    /// Replace the conversation, continuing from the active branch of
+    /// `history`.
+    pub fn set_history(&mut self, history: ConversationTree) {
+        self.session.set_history(history);
+    }
+
+    /// Carry on `previous`'s conversation, and its count of tokens sent, with
+    /// this client's provider, leaving `previous` empty.
+    pub fn continuing(mut self, previous: &mut Client) -> Self {
+        self.session.continuing(&mut previous.session);
         self
     }
 
+    /// The messages sent with the next request: the active branch of the
+    /// history, less any dropped or summarized to fit the token budget.
+    pub fn context(&self) -> Vec<Message> {
+        self.session.context()
+    }
+
+    /// Every message exchanged, branching wherever a reply was regenerated or
+    /// a prompt undone.
+    pub fn history(&self) -> &ConversationTree {
+        self.session.history()
+    }
+
     pub fn with(mut self, config: ClientConfig) -> Self {
-        self.config = config;
+        self.session.config = config;
         self
     }
 
+    /// Count tokens with the given tokenizer rather than the one resolved
+    /// from the provider's advertised encoding.
+    pub fn with_tokenizer<T: Tokenizer + 'static>(self, tokenizer: T) -> Self {
+        self.session.set_tokenizer(Box::new(tokenizer));
+        self
+    }
+
+    /// Offer `tool` to the model, answering its calls with `handler`. While
+    /// any tools are registered, `send` keeps dispatching the model's calls
+    /// and returning their results until it gives a final answer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use air::client::Client;
+    /// use air::host::OpenAI;
+    /// use air::tool::Tool;
+    ///
+    /// let tool = Tool::new("get_time", "The current UTC time");
+    /// let client = Client::new(OpenAI::new("gpt-4o", "my-api-key"))
+    ///     .with_tool(tool, |_| Ok("12:00".to_string()));
+    /// ```
+    pub fn with_tool<F>(mut self, tool: Tool, handler: F) -> Self
+    where
+        F: Fn(serde_json::Value) -> anyhow::Result<String> + 'static,
+    {
+        self.tools.retain(|t| t.name != tool.name);
+        self.handlers.insert(tool.name.clone(), Box::new(handler));
+        self.tools.push(tool);
+        self
+    }
+
+    /// The tokenizer for the provider's model, resolved on first use.
+    pub fn tokenizer(&self) -> &dyn Tokenizer {
+        self.session.tokenizer()
+    }
+
+    /// Why the tokenizer for the provider's encoding could not be loaded from
+    /// `tokenizer_dir`, if it could not; token counts are then estimated.
+    pub fn tokenizer_error(&self) -> Option<&anyhow::Error> {
+        self.session.tokenizer_error()
+    }
+
+    /// Number of tokens in the current context, as counted locally.
+    pub fn count_tokens(&self) -> usize {
+        self.session.count_tokens()
+    }
+
+    /// Tokens sent to the provider so far, as it reported them, or `None` if
+    /// any response omitted the count.
+    pub fn tokens_sent(&self) -> Option<u64> {
+        self.session.tokens_sent()
+    }
+
+    /// Metadata describing the most recent response, for recording it with
+    /// `Transcript::record_with`.
+    pub fn last_metadata(&self) -> Option<&Metadata> {
+        self.session.last_metadata()
+    }
+
+    /// Names of the models the provider offers.
+    pub fn models(&self) -> Result<Vec<String>, ProviderError> {
+        self.provider.models(&self.http_client)
+    }
+
+    /// Start the conversation afresh on a new branch of the history, keeping
+    /// its opening system messages if the config sets `retain_system`.
     pub fn clear(&mut self) {
-        self.context.clear();
+        self.session.clear();
+    }
+
+    /// Set the system message opening the context, replacing any already
+    /// there. In the history, the conversation continues on a new branch
+    /// opened by the message.
+    pub fn set_system<S: Into<String>>(&mut self, content: S) {
+        self.session.set_system(Message::system(content));
     }
 
-    /// Send a message to the model alongside the existing context
+    /// Remove the last prompt and everything after it, which stays in the
+    /// history. Returns false if there is no prompt to remove.
+    pub fn undo(&mut self) -> bool {
+        self.session.undo()
+    }
+
+    /// Send a message to the model alongside the existing context. Tool calls
+    /// in the response are answered by the registered handlers, with each
+    /// call and result kept in the context, until the model's final answer.
     pub fn send(&mut self, content: Message) -> Result<&Message, ProviderError> {
-        self.context.push(content);
-        let (message, usage) = self.provider.send(&self.context, &self.http_client)?;
+        self.push(content)?;
+        self.complete()
+    }
+
+    /// Ask the model to reply to the context, answering any tool calls.
+    fn complete(&mut self) -> Result<&Message, ProviderError> {
+        for _ in 0..=MAX_TOOL_ROUNDS {
+            let started = Instant::now();
+            let context = self.session.context();
+            let request = Request::new(&context)
+                .with_tools(&self.tools)
+                .with_parameters(&self.session.config.parameters);
+            let Completion {
+                message,
+                usage,
+                finish_reason,
+            } = self.retrying(|| self.provider.send(&request, &self.http_client))?;
+            let calls = message.tool_calls.clone();
+            self.session
+                .answer(message, &self.provider, started, usage, finish_reason);
+            if calls.is_empty() {
+                return Ok(self.session.last());
+            }
+            for call in calls {
+                let result = self.call(&call);
+                self.session.remember(Message::tool(call.id, result));
+            }
+        }
+        Err(ProviderError::ToolRoundsExceeded(MAX_TOOL_ROUNDS))
+    }
 
-        self.context.push(message);
-        self.tokens_sent = match (usage.total_tokens, self.tokens_sent) {
-            (_, None) | (None, Some(_)) => None,
-            (Some(x), Some(y)) => Some(x + y),
+    /// Ask the model again for a reply to the last user message. The new
+    /// reply, with any tool calls, replaces the previous one in the context,
+    /// which stays in the history as an alternative. If the new request
+    /// fails, the previous reply is restored and nothing of the attempt kept.
+    pub fn regenerate(&mut self) -> Result<&Message, ProviderError> {
+        let checkpoint = self.session.checkpoint();
+        self.session.rewind()?;
+        if let Err(err) = self
+            .enforce_budget()
+            .and_then(|_| self.complete().map(|_| ()))
+        {
+            self.session.restore(checkpoint);
+            return Err(err);
+        }
+        Ok(self.session.last())
+    }
+
+    /// Run the handler for `call`, describing any failure as the result so the
+    /// model can correct itself.
+    fn call(&self, call: &ToolCall) -> String {
+        let Some(handler) = self.handlers.get(&call.name) else {
+            return format!("Error: no tool named `{}`", call.name);
+        };
+        let arguments = match call.arguments.trim() {
+            "" => Ok(serde_json::json!({})),
+            arguments => serde_json::from_str(arguments),
         };
+        match arguments {
+            Err(err) => format!("Error: arguments are not valid JSON: {err}"),
+            Ok(arguments) => match handler(arguments) {
+                Ok(result) => result,
+                Err(err) => format!("Error: {err}"),
+            },
+        }
+    }
+
+    /// Send a message to the model alongside the existing context, passing
+    /// each piece of the response to `on_delta` as it arrives. The assembled
+    /// response is appended to the context once the stream completes. With
+    /// tools registered, the exchange runs through `send` and the final
+    /// answer is passed to `on_delta` whole.
+    pub fn send_stream<F: FnMut(&str)>(
+        &mut self,
+        content: Message,
+        mut on_delta: F,
+    ) -> Result<&Message, ProviderError> {
+        if !self.tools.is_empty() {
+            let response = self.send(content)?;
+            on_delta(&response.content);
+            return Ok(response);
+        }
+
+        self.push(content)?;
+        // only establishing the stream is retried; a broken stream is an error
+        let started = Instant::now();
+        let context = self.session.context();
+        let request = Request::new(&context).with_parameters(&self.session.config.parameters);
+        let stream = self.retrying(|| self.provider.send_stream(&request, &self.http_client))?;
 
-        let model_response = self.context.last().unwrap();
-        Ok(model_response)
+        let mut text = String::new();
+        let mut usage = Usage::new();
+        let mut finish_reason = None;
+        for delta in stream {
+            let delta = delta?;
+            on_delta(&delta.content);
+            text.push_str(&delta.content);
+            if let Some(u) = delta.usage {
+                usage = u;
+            }
+            if delta.finish_reason.is_some() {
+                finish_reason = delta.finish_reason;
+            }
+        }
+
+        let message = Message::assistant(text);
+        self.session
+            .answer(message, &self.provider, started, usage, finish_reason);
+
+        Ok(self.session.last())
+    }
+
+    /// Add an outgoing message to the conversation, applying the token
+    /// budget. If the budget refuses the message, nothing is added.
+    fn push(&mut self, content: Message) -> Result<(), ProviderError> {
+        let checkpoint = self.session.checkpoint();
+        self.session.remember(content);
+        if let Err(err) = self.enforce_budget() {
+            self.session.restore(checkpoint);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Apply the configured `BudgetPolicy` if the context's token count
+    /// exceeds `max_tokens`.
+    fn enforce_budget(&mut self) -> Result<(), ProviderError> {
+        let Some(overflow) = self.session.overflow()? else {
+            return Ok(());
+        };
+        let summary = match overflow.summary_request() {
+            Some(request) => Some(self.summarize(&request)?),
+            None => None,
+        };
+        self.session.compact(overflow, summary)
+    }
+
+    /// Ask the provider for the summary `request` describes, as a system
+    /// message standing in for the messages summarized.
+    fn summarize(&mut self, request: &[Message]) -> Result<Message, ProviderError> {
+        let request = Request::new(request);
+        let summary = self.retrying(|| self.provider.send(&request, &self.http_client))?;
+        self.session.track(&summary.usage);
+
+        Ok(budget::summary_message(&summary.message.content))
+    }
+
+    /// Call `request`, retrying transient failures per the configured
+    /// `RetryPolicy`.
+    fn retrying<T>(
+        &self,
+        request: impl Fn() -> Result<T, ProviderError>,
+    ) -> Result<T, ProviderError> {
+        let mut retry = 0;
+        loop {
+            match request() {
+                Ok(value) => return Ok(value),
+                Err(err) => match self.session.retry_delay(retry, &err) {
+                    None => return Err(err),
+                    Some(delay) => {
+                        sleep(delay);
+                        retry += 1;
+                    }
+                },
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::host::OpenAI;
+    use crate::test_utils::{MockResponse, MockServer};
+    use crate::{Delta, DeltaStream};
 
     use super::*;
 
+    /// Provider streaming a fixed response split into words.
+    struct Scripted(&'static str);
+
+    impl Display for Scripted {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Scripted")
+        }
+    }
+
+    impl Provider for Scripted {
+        fn models(&self, _: &reqwest::blocking::Client) -> Result<Vec<String>, ProviderError> {
+            Ok(vec!["scripted".to_string()])
+        }
+
+        fn send(
+            &self,
+            _: &Request,
+            _: &reqwest::blocking::Client,
+        ) -> Result<Completion, ProviderError> {
+            Ok(Completion::new(Message::assistant(self.0), Usage::new()))
+        }
+
+        fn send_stream(
+            &self,
+            _: &Request,
+            _: &reqwest::blocking::Client,
+        ) -> Result<DeltaStream, ProviderError> {
+            let words = self.0.split_inclusive(' ').map(|word| {
+                Ok(Delta {
+                    content: word.to_string(),
+                    ..Default::default()
+                })
+            });
+            let usage = Delta {
+                usage: serde_json::from_str(r#"{"total_tokens":7}"#).ok(),
+                ..Default::default()
+            };
+            Ok(Box::new(words.chain(std::iter::once(Ok(usage)))))
+        }
+    }
+
+    #[test]
+    fn test_client_send_stream_assembles() {
+        let mut client = Client::new(Scripted("The answer is 42"));
+        let mut pieces = Vec::new();
+        let response = client
+            .send_stream(Message::user("Question?"), |delta| {
+                pieces.push(delta.to_string())
+            })
+            .unwrap();
+
+        assert_eq!(response, &Message::assistant("The answer is 42"));
+        assert_eq!(pieces.concat(), "The answer is 42");
+        assert!(pieces.len() > 1);
+        assert_eq!(client.context().len(), 2);
+        assert_eq!(client.tokens_sent(), Some(7));
+    }
+
+    #[test]
+    fn test_client_regenerate_replaces_reply() {
+        let mut client = Client::new(Scripted("Blue")).with_context(vec![
+            Message::system("Be brief"),
+            Message::user("Name a colour"),
+            Message::assistant("Red"),
+        ]);
+        let response = client.regenerate().unwrap();
+        assert_eq!(response, &Message::assistant("Blue"));
+        assert_eq!(client.context().len(), 3);
+        assert_eq!(client.context()[1], Message::user("Name a colour"));
+
+        let mut client = Client::new(Scripted("Blue")).with_context(vec![Message::system("Hi")]);
+        assert!(matches!(client.regenerate(), Err(ProviderError::NoPrompt)));
+    }
+
+    #[test]
+    fn test_client_regenerate_keeps_history() {
+        let mut client = Client::new(Scripted("Blue")).with_context(vec![
+            Message::user("Name a colour"),
+            Message::assistant("Red"),
+        ]);
+        client.regenerate().unwrap();
+        let reply = client.history().active().unwrap();
+        let replies = client.history().siblings(reply);
+        let contents: Vec<_> = replies
+            .iter()
+            .map(|&id| client.history().get(id).unwrap().message.content.as_str())
+            .collect();
+        assert_eq!(contents, vec!["Red", "Blue"]);
+        assert_eq!(client.history().messages(), client.context());
+
+        assert!(client.undo());
+        assert!(client.context().is_empty());
+        client.send(Message::user("Name a shape")).unwrap();
+        assert_eq!(client.history().len(), 5);
+        assert_eq!(client.history().messages(), client.context());
+    }
+
+    #[test]
+    fn test_client_regenerate_restores_on_error() {
+        let context = vec![Message::user("Name a colour"), Message::assistant("Red")];
+        let mut client = budgeted(BudgetPolicy::Refuse, 1).with_context(context.clone());
+        assert!(client.regenerate().is_err());
+        assert_eq!(client.context(), context);
+        assert_eq!(client.history().messages(), context);
+    }
+
+    #[test]
+    fn test_client_regenerate_prunes_failed_attempt() {
+        let call = serde_json::json!({
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "pick", "arguments": "{}"}
+                    }]
+                },
+                "finish_reason": "tool_calls",
+                "logprobs": null
+            }]
+        });
+        let server = MockServer::start(vec![
+            MockResponse::json(200, call),
+            MockResponse::new(400, "bad request"),
+        ]);
+        let config = ClientConfig {
+            retry: RetryPolicy::none(),
+            ..Default::default()
+        };
+        let provider = OpenAI::new("gpt-4o", "key").with_base_url(server.url());
+        let context = vec![Message::user("Name a colour"), Message::assistant("Red")];
+        let mut client = Client::new(provider)
+            .with(config)
+            .with_context(context.clone())
+            .with_tool(crate::tool::Tool::new("pick", "Pick a colour"), |_| {
+                Ok("Green".to_string())
+            });
+
+        assert!(client.regenerate().is_err());
+        assert_eq!(client.history().len(), 2);
+        assert_eq!(client.history().messages(), context);
+    }
+
+    #[test]
+    fn test_client_system_message() {
+        let mut client = Client::new(Scripted("Hi"))
+            .with_context(vec![Message::system("Be brief"), Message::user("Hello")]);
+        client.set_system("Be verbose");
+        client.clear();
+        assert!(client.context().is_empty());
+
+        let config = ClientConfig {
+            retain_system: true,
+            ..Default::default()
+        };
+        let mut client = Client::new(Scripted("Hi"))
+            .with(config)
+            .with_context(vec![Message::user("Hello")]);
+        client.set_system("Be brief");
+        client.set_system("Be verbose");
+        assert_eq!(client.history().messages(), client.context());
+        client.clear();
+        assert_eq!(client.context(), vec![Message::system("Be verbose")]);
+        assert_eq!(client.history().messages(), client.context());
+    }
+
     #[test]
     fn test_client_context_init() {
         let name = String::from("gpt-3.5-turbo");
         let key = String::from("api-key");
         let client = Client::new(OpenAI::new(name, key));
-        assert!(client.context.is_empty());
+        assert!(client.context().is_empty());
+    }
+
+    #[test]
+    fn test_client_tokenizer_error() {
+        let config = ClientConfig {
+            tokenizer_dir: Some(std::env::temp_dir().join("air-no-such-tokenizers")),
+            ..Default::default()
+        };
+        let client = Client::new(OpenAI::new("gpt-4o", "key")).with(config);
+        assert_eq!(client.count_tokens(), 0);
+        assert!(client.tokenizer_error().is_some());
+
+        let client = Client::new(OpenAI::new("gpt-4o", "key"));
+        assert!(client.tokenizer_error().is_none());
+    }
+
+    fn budgeted(policy: BudgetPolicy, max_tokens: usize) -> Client {
+        let config = ClientConfig {
+            max_tokens: Some(max_tokens),
+            budget_policy: policy,
+            ..Default::default()
+        };
+        let context = vec![
+            Message::system("Be brief."),
+            Message::user("An opening question. ".repeat(5)),
+            Message::assistant("An opening answer. ".repeat(5)),
+        ];
+        Client::new(Scripted("Short"))
+            .with(config)
+            .with_context(context)
+    }
+
+    #[test]
+    fn test_client_budget_refuse() {
+        let mut client = budgeted(BudgetPolicy::Refuse, 20);
+        let response = client.send(Message::user("Next?"));
+        match response {
+            Err(ProviderError::BudgetExceeded { limit, estimated }) => {
+                assert_eq!(limit, 20);
+                assert!(estimated > limit);
+            }
+            _ => panic!("Expected budget to be exceeded"),
+        }
+        assert_eq!(client.context().len(), 3);
+    }
+
+    #[test]
+    fn test_client_budget_truncate() {
+        let mut client = budgeted(BudgetPolicy::Truncate, 20);
+        client.send(Message::user("Next?")).unwrap();
+        assert_eq!(
+            client.context(),
+            vec![
+                Message::system("Be brief."),
+                Message::user("Next?"),
+                Message::assistant("Short"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_client_budget_summarize() {
+        let mut client = budgeted(BudgetPolicy::Summarize, 40);
+        client.send(Message::user("Next?")).unwrap();
+        assert_eq!(client.context().len(), 4);
+        assert_eq!(client.context()[0], Message::system("Be brief."));
+        assert_eq!(
+            client.context()[1],
+            Message::system("Summary of the earlier conversation: Short")
+        );
+        assert_eq!(client.context()[2], Message::user("Next?"));
+    }
+
+    #[test]
+    fn test_client_budget_unreachable() {
+        let mut client = budgeted(BudgetPolicy::Truncate, 5);
+        let response = client.send(Message::user("Next?"));
+        assert!(matches!(
+            response,
+            Err(ProviderError::BudgetExceeded { .. })
+        ));
+        assert_eq!(client.context().len(), 3);
+    }
+
+    #[test]
+    fn test_client_retries_transient_errors() {
+        let body = serde_json::json!({
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hello, user!"},
+                "finish_reason": "stop",
+                "logprobs": null
+            }],
+            "usage": {"prompt_tokens": 9, "completion_tokens": 4, "total_tokens": 13}
+        });
+        let server = MockServer::start(vec![
+            MockResponse::new(429, "slow down").with_header("Retry-After", "0"),
+            MockResponse::new(503, "overloaded"),
+            MockResponse::json(200, body),
+        ]);
+        let config = ClientConfig {
+            retry: RetryPolicy {
+                base_delay: std::time::Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let provider = OpenAI::new("gpt-4", "key").with_base_url(server.url());
+        let mut client = Client::new(provider).with(config);
+
+        let response = client.send(Message::user("Hello, assistant!")).unwrap();
+        assert_eq!(response, &Message::assistant("Hello, user!"));
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(client.context().len(), 2);
+
+        let metadata = client.last_metadata().unwrap();
+        assert_eq!(metadata.provider.as_deref(), Some("OpenAI gpt-4"));
+        assert_eq!(metadata.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(metadata.usage.as_ref().unwrap().total_tokens, Some(13));
+        assert!(metadata.latency.is_some());
+
+        // the history keeps what a transcript would record
+        let entries = client.history().entries();
+        assert_eq!(entries[1].metadata.as_ref(), Some(metadata));
+        assert!(entries[0].metadata.as_ref().unwrap().timestamp.is_some());
+    }
+
+    #[test]
+    fn test_client_retries_exhausted() {
+        let server = MockServer::start(vec![
+            MockResponse::new(429, "slow down").with_header("Retry-After", "0"),
+            MockResponse::new(429, "slow down").with_header("Retry-After", "0"),
+        ]);
+        let config = ClientConfig {
+            retry: RetryPolicy {
+                max_retries: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let provider = OpenAI::new("gpt-4", "key").with_base_url(server.url());
+        let mut client = Client::new(provider).with(config);
+
+        let response = client.send(Message::user("Hello, assistant!"));
+        assert!(matches!(response, Err(ProviderError::RateLimited { .. })));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_client_tool_loop() {
+        let call = serde_json::json!({
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "add", "arguments": "{\"a\": 2, \"b\": 3}"}
+                    }]
+                },
+                "finish_reason": "tool_calls",
+                "logprobs": null
+            }],
+            "usage": {"prompt_tokens": 20, "completion_tokens": 10, "total_tokens": 30}
+        });
+        let answer = serde_json::json!({
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "2 + 3 = 5"},
+                "finish_reason": "stop",
+                "logprobs": null
+            }],
+            "usage": {"prompt_tokens": 40, "completion_tokens": 5, "total_tokens": 45}
+        });
+        let server = MockServer::start(vec![
+            MockResponse::json(200, call),
+            MockResponse::json(200, answer),
+        ]);
+        let add = crate::tool::Tool::new("add", "Add two numbers");
+        let provider = OpenAI::new("gpt-4o", "key").with_base_url(server.url());
+        let mut client = Client::new(provider).with_tool(add, |args| {
+            let sum = args["a"].as_i64().unwrap_or(0) + args["b"].as_i64().unwrap_or(0);
+            Ok(sum.to_string())
+        });
+
+        let response = client.send(Message::user("What is 2 + 3?")).unwrap();
+        assert_eq!(response, &Message::assistant("2 + 3 = 5"));
+        assert_eq!(client.context().len(), 4);
+        assert_eq!(client.context()[2], Message::tool("call_1", "5"));
+        assert_eq!(client.tokens_sent(), Some(75));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].json()["tools"][0]["function"]["name"], "add");
+        assert_eq!(
+            requests[1].json()["messages"][1]["tool_calls"][0]["id"],
+            "call_1"
+        );
+        assert_eq!(
+            requests[1].json()["messages"][2],
+            serde_json::json!({"role": "tool", "content": "5", "tool_call_id": "call_1"})
+        );
+    }
+
+    #[test]
+    fn test_client_tool_errors_reported() {
+        let client = Client::new(Scripted("Done"))
+            .with_tool(crate::tool::Tool::new("fail", "Always fails"), |_| {
+                anyhow::bail!("disk on fire")
+            });
+        let call = |name: &str, arguments: &str| ToolCall {
+            id: "call_1".to_string(),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        };
+        assert_eq!(client.call(&call("fail", "{}")), "Error: disk on fire");
+        assert!(client
+            .call(&call("fail", "{oops"))
+            .starts_with("Error: arguments"));
+        assert_eq!(
+            client.call(&call("missing", "{}")),
+            "Error: no tool named `missing`"
+        );
+    }
+
+    #[test]
+    fn test_client_connection_failed() {
+        // bind then drop a listener so the port is (almost certainly) closed
+        let url = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let config = ClientConfig {
+            retry: RetryPolicy::none(),
+            ..Default::default()
+        };
+        let mut client = Client::new(OpenAI::new("gpt-4", "key").with_base_url(url)).with(config);
+
+        let response = client.send(Message::user("Hello, world!"));
+        assert!(matches!(response, Err(ProviderError::ConnectionFailed(_))));
     }
 
     #[test]
     fn test_client_bad_api_key() {
+        let body = serde_json::json!({
+            "error": {"message": "Incorrect API key provided", "code": "invalid_api_key"}
+        });
+        let server = MockServer::start(vec![MockResponse::json(401, body)]);
+
         let name = String::from("gpt-3.5-turbo");
         let key = String::from("api-key");
-        let mut client = Client::new(OpenAI::new(name, key));
+        let mut client = Client::new(OpenAI::new(name, key).with_base_url(server.url()));
 
         let message = Message::user("Hello, world!");
         let response = client.send(message);
         assert!(response.is_err());
         match response {
-            Err(ProviderError::HttpError(code)) => assert_eq!(code, 401),
+            Err(ProviderError::AuthenticationFailed { error: Some(error) }) => {
+                assert_eq!(error.message, "Incorrect API key provided");
+                assert_eq!(error.code.as_deref(), Some("invalid_api_key"));
+            }
             _ => panic!("Expected 401 Unauthorized error"),
         };
     }
@@ -0,0 +1,166 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use crate::ProviderError;
+
+/// Exponential backoff for transient provider errors (rate limiting,
+/// server-side failures, timeouts and failed connections). A `Retry-After`
+/// hint from the server takes precedence over the computed delay, though no
+/// delay exceeds `max_delay`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; zero disables retrying
+    pub max_retries: u32,
+
+    /// Delay before the first retry, doubling on each subsequent one
+    pub base_delay: Duration,
+
+    /// Upper bound on any delay, computed or asked for by the server
+    pub max_delay: Duration,
+
+    /// Randomize each delay between half and all of its value, spreading out
+    /// clients that failed at the same moment
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Delay before retrying after the `retry`-th retry (starting at zero for
+    /// the first) would be attempted following `err`, or `None` if the error
+    /// is permanent or retries are exhausted.
+    pub fn delay(&self, retry: u32, err: &ProviderError) -> Option<Duration> {
+        if retry >= self.max_retries {
+            return None;
+        }
+        match err {
+            ProviderError::RateLimited {
+                retry_after: Some(delay),
+                ..
+            } => Some((*delay).min(self.max_delay)),
+            ProviderError::RateLimited { .. }
+            | ProviderError::Timeout
+            | ProviderError::ConnectionFailed(_) => Some(self.backoff(retry)),
+            ProviderError::HttpError { status, .. } if status.is_server_error() => {
+                Some(self.backoff(retry))
+            }
+            _ => None,
+        }
+    }
+
+    fn backoff(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        match self.jitter {
+            true => delay.mul_f64(0.5 + 0.5 * random_unit()),
+            false => delay,
+        }
+    }
+}
+
+/// A random value in `[0, 1)`, drawn from the standard library's randomly
+/// seeded hasher to avoid a dependency for jitter alone.
+fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            jitter: false,
+        }
+    }
+
+    #[test]
+    fn test_retry_backoff_doubles_and_caps() {
+        let err = ProviderError::HttpError {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            error: None,
+        };
+        let delays: Vec<_> = (0..4).map(|retry| policy().delay(retry, &err)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(300)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_retry_honours_retry_after() {
+        let err = ProviderError::RateLimited {
+            retry_after: Some(Duration::from_secs(2)),
+            error: None,
+        };
+        let patient = RetryPolicy {
+            max_delay: Duration::from_secs(5),
+            ..policy()
+        };
+        assert_eq!(patient.delay(0, &err), Some(Duration::from_secs(2)));
+        assert_eq!(policy().delay(0, &err), Some(Duration::from_millis(300)));
+    }
+
+    #[test]
+    fn test_retry_permanent_errors() {
+        let err = ProviderError::AuthenticationFailed { error: None };
+        assert_eq!(policy().delay(0, &err), None);
+        let err = ProviderError::HttpError {
+            status: StatusCode::BAD_REQUEST,
+            error: None,
+        };
+        assert_eq!(policy().delay(0, &err), None);
+        assert_eq!(policy().delay(0, &ProviderError::EmptyResponse), None);
+        let err = ProviderError::RateLimited {
+            retry_after: None,
+            error: None,
+        };
+        assert_eq!(RetryPolicy::none().delay(0, &err), None);
+    }
+
+    #[test]
+    fn test_retry_jitter_bounds() {
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy()
+        };
+        let err = ProviderError::RateLimited {
+            retry_after: None,
+            error: None,
+        };
+        for _ in 0..20 {
+            let delay = policy.delay(1, &err).unwrap();
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use std::{fmt::Display, sync::OnceLock, time::Duration, time::Instant};
+
+use chrono::Utc;
+
+use super::budget::{self, Compaction};
+use super::{BudgetPolicy, ClientConfig};
+use crate::tokenizer::{self, CharHeuristic, Encoding, Tokenizer};
+use crate::transcript::{Entry, Metadata};
+use crate::tree::{ConversationTree, NodeId};
+use crate::{host::Usage, Message, ProviderError, Role};
+
+/// The state `Client` and `AsyncClient` share, and everything they do with
+/// it that does not involve waiting on the provider: the conversation and its
+/// token budget, retry decisions and usage tracking.
+pub(super) struct Session {
+    pub(super) config: ClientConfig,
+    encoding: Option<Encoding>,
+    tokenizer: OnceLock<Resolved>,
+    history: ConversationTree,
+    compaction: Compaction,
+    tokens_sent: Option<u64>,
+    metadata: Option<Metadata>,
+}
+
+/// The tokenizer counting a session's tokens, and why the one for the
+/// provider's encoding could not be loaded if a heuristic stands in for it.
+struct Resolved {
+    tokenizer: Box<dyn Tokenizer>,
+    error: Option<anyhow::Error>,
+}
+
+/// Where a conversation stood before an attempt to extend it.
+pub(super) struct Checkpoint {
+    len: usize,
+    active: Option<NodeId>,
+    compaction: Compaction,
+}
+
+/// Messages to leave out so that the context fits the token budget.
+pub(super) struct Overflow {
+    context: Vec<Message>,
+    dropped: Vec<usize>,
+    policy: BudgetPolicy,
+    exceeded: ProviderError,
+}
+
+impl Overflow {
+    /// A request asking the model to condense the dropped messages, if the
+    /// policy replaces them with a summary.
+    pub(super) fn summary_request(&self) -> Option<[Message; 2]> {
+        (self.policy == BudgetPolicy::Summarize)
+            .then(|| budget::summary_request(&self.context, &self.dropped))
+    }
+}
+
+impl Session {
+    /// A session for a provider whose models use `encoding`.
+    pub(super) fn new(encoding: Option<Encoding>) -> Self {
+        Self {
+            config: ClientConfig::default(),
+            encoding,
+            tokenizer: OnceLock::new(),
+            history: ConversationTree::new(),
+            compaction: Compaction::default(),
+            tokens_sent: Some(0),
+            metadata: None,
+        }
+    }
+
+    pub(super) fn set_tokenizer(&self, tokenizer: Box<dyn Tokenizer>) {
+        let _ = self.tokenizer.set(Resolved {
+            tokenizer,
+            error: None,
+        });
+    }
+
+    pub(super) fn tokenizer(&self) -> &dyn Tokenizer {
+        self.resolved().tokenizer.as_ref()
+    }
+
+    pub(super) fn tokenizer_error(&self) -> Option<&anyhow::Error> {
+        self.resolved().error.as_ref()
+    }
+
+    /// The tokenizer, resolved on first use.
+    fn resolved(&self) -> &Resolved {
+        self.tokenizer.get_or_init(|| {
+            let dir = self.config.tokenizer_dir.as_deref();
+            match tokenizer::resolve(self.encoding, dir) {
+                Ok(tokenizer) => Resolved {
+                    tokenizer,
+                    error: None,
+                },
+                Err(err) => Resolved {
+                    tokenizer: Box::new(CharHeuristic),
+                    error: Some(err),
+                },
+            }
+        })
+    }
+
+    pub(super) fn count_tokens(&self) -> usize {
+        self.tokenizer().count_messages(&self.context())
+    }
+
+    pub(super) fn tokens_sent(&self) -> Option<u64> {
+        self.tokens_sent
+    }
+
+    pub(super) fn last_metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    pub(super) fn context(&self) -> Vec<Message> {
+        self.compaction.context(&self.history)
+    }
+
+    pub(super) fn history(&self) -> &ConversationTree {
+        &self.history
+    }
+
+    pub(super) fn set_history(&mut self, history: ConversationTree) {
+        self.history = history;
+        self.compaction = Compaction::default();
+    }
+
+    /// Take over `previous`'s conversation and count of tokens sent.
+    pub(super) fn continuing(&mut self, previous: &mut Session) {
+        self.history = std::mem::take(&mut previous.history);
+        self.compaction = std::mem::take(&mut previous.compaction);
+        self.tokens_sent = previous.tokens_sent;
+    }
+
+    /// Start the conversation afresh on a new branch of the history, keeping
+    /// its opening system messages if the config sets `retain_system`.
+    pub(super) fn clear(&mut self) {
+        let kept =
+            self.history.path().into_iter().take_while(|&id| {
+                self.config.retain_system && self.message(id).role == Role::System
+            });
+        self.resume(kept.last());
+    }
+
+    /// Open the conversation with `message`, continuing it on a new branch.
+    pub(super) fn set_system(&mut self, message: Message) {
+        let path = self.history.path();
+        let rest = match path.first() {
+            Some(&root) if self.message(root).role == Role::System => &path[1..],
+            _ => &path[..],
+        };
+        self.history.reset();
+        self.remember(message);
+        for &id in rest {
+            let node = self.history.get(id).unwrap();
+            let copy = self.history.push_entry(Entry {
+                message: node.message.clone(),
+                metadata: node.metadata.clone(),
+            });
+            self.compaction.carry(id, copy);
+        }
+    }
+
+    /// Continue the conversation from before its last prompt. Returns false
+    /// if there is no prompt.
+    pub(super) fn undo(&mut self) -> bool {
+        let Some(prompt) = self.last_prompt() else {
+            return false;
+        };
+        self.resume(self.history.get(prompt).unwrap().parent());
+        true
+    }
+
+    /// Continue the conversation from its last prompt, so that the next
+    /// message added is a new reply to it.
+    pub(super) fn rewind(&mut self) -> Result<(), ProviderError> {
+        let prompt = self.last_prompt().ok_or(ProviderError::NoPrompt)?;
+        self.history.fork(prompt);
+        self.compaction.keep(prompt);
+        Ok(())
+    }
+
+    /// Add a message to the conversation, noting when it was added.
+    pub(super) fn remember(&mut self, message: Message) {
+        self.history.push_entry(Entry {
+            message,
+            metadata: Some(Metadata::now()),
+        });
+    }
+
+    /// Add the reply `message` from `provider`, requested at `started`, to
+    /// the conversation, tracking its usage and describing it as the most
+    /// recent response.
+    pub(super) fn answer(
+        &mut self,
+        message: Message,
+        provider: &dyn Display,
+        started: Instant,
+        usage: Usage,
+        finish_reason: Option<String>,
+    ) {
+        self.track(&usage);
+        let metadata = describe(provider, started, usage, finish_reason);
+        self.history.push_entry(Entry {
+            message,
+            metadata: Some(metadata.clone()),
+        });
+        self.metadata = Some(metadata);
+    }
+
+    /// The last message of the conversation, e.g. the reply just received.
+    pub(super) fn last(&self) -> &Message {
+        self.message(self.history.active().expect("message in history"))
+    }
+
+    pub(super) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            len: self.history.len(),
+            active: self.history.active(),
+            compaction: self.compaction.clone(),
+        }
+    }
+
+    /// Return to `checkpoint`, discarding everything added since.
+    pub(super) fn restore(&mut self, checkpoint: Checkpoint) {
+        self.history.truncate(checkpoint.len);
+        self.resume(checkpoint.active);
+        self.compaction = checkpoint.compaction;
+    }
+
+    /// What the configured `BudgetPolicy` leaves out if the context's token
+    /// count exceeds `max_tokens`, or an error if it cannot be brought within
+    /// it.
+    pub(super) fn overflow(&self) -> Result<Option<Overflow>, ProviderError> {
+        let Some(limit) = self.config.max_tokens else {
+            return Ok(None);
+        };
+        let context = self.context();
+        let estimated = self.tokenizer().count_messages(&context);
+        if estimated <= limit {
+            return Ok(None);
+        }
+
+        let exceeded = ProviderError::BudgetExceeded { estimated, limit };
+        let count = |messages: &[Message]| self.tokenizer().count_messages(messages);
+        let overflow = budget::overflow(&context, limit, count);
+        match (self.config.budget_policy, overflow) {
+            (BudgetPolicy::Refuse, _) | (_, None) => Err(exceeded),
+            (policy, Some(dropped)) => Ok(Some(Overflow {
+                context,
+                dropped,
+                policy,
+                exceeded,
+            })),
+        }
+    }
+
+    /// Leave out the messages of `overflow`, with `summary` (if any) in their
+    /// place, unless the context would still exceed the budget.
+    pub(super) fn compact(
+        &mut self,
+        overflow: Overflow,
+        summary: Option<Message>,
+    ) -> Result<(), ProviderError> {
+        let mut compaction = self.compaction.clone();
+        compaction.exclude(&self.history, &overflow.dropped, summary);
+        let limit = self.config.max_tokens.unwrap_or(usize::MAX);
+        if self
+            .tokenizer()
+            .count_messages(&compaction.context(&self.history))
+            > limit
+        {
+            return Err(overflow.exceeded);
+        }
+        self.compaction = compaction;
+        Ok(())
+    }
+
+    /// How long to wait before retrying after `err`, per the configured
+    /// `RetryPolicy`, or `None` to give up.
+    pub(super) fn retry_delay(&self, retry: u32, err: &ProviderError) -> Option<Duration> {
+        let delay = self.config.retry.delay(retry, err)?;
+        if self.config.verbose {
+            eprintln!("{err}; retrying in {:.1}s", delay.as_secs_f64());
+        }
+        Some(delay)
+    }
+
+    /// Accumulate reported token usage, giving up on the count once any
+    /// response omits it.
+    pub(super) fn track(&mut self, usage: &Usage) {
+        self.tokens_sent = accumulate(self.tokens_sent, usage);
+    }
+
+    /// Continue the conversation from `id`, or start a new one if `None`.
+    fn resume(&mut self, id: Option<NodeId>) {
+        match id {
+            Some(id) => {
+                self.history.fork(id);
+            }
+            None => self.history.reset(),
+        }
+    }
+
+    /// The last user message in the history's active branch.
+    fn last_prompt(&self) -> Option<NodeId> {
+        let path = self.history.path();
+        path.into_iter()
+            .rev()
+            .find(|&id| self.message(id).role == Role::User)
+    }
+
+    /// The message at `id` in the history.
+    fn message(&self, id: NodeId) -> &Message {
+        &self.history.get(id).expect("node in history").message
+    }
+}
+
+/// Add reported usage to a running total, which becomes unknown once any
+/// response omits it.
+fn accumulate(tokens_sent: Option<u64>, usage: &Usage) -> Option<u64> {
+    match (usage.total_tokens, tokens_sent) {
+        (_, None) | (None, Some(_)) => None,
+        (Some(x), Some(y)) => Some(x + y),
+    }
+}
+
+/// Metadata for a response from `provider` requested at `started`.
+fn describe(
+    provider: &dyn Display,
+    started: Instant,
+    usage: Usage,
+    finish_reason: Option<String>,
+) -> Metadata {
+    Metadata {
+        timestamp: Some(Utc::now()),
+        provider: Some(provider.to_string()),
+        usage: Some(usage),
+        latency: Some(started.elapsed()),
+        finish_reason,
+    }
+}
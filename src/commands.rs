@@ -0,0 +1,257 @@
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Helper};
+use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+
+/// Commands available at the REPL prompt, with their arguments and a short
+/// description, in the order `/help` lists them.
+pub const COMMANDS: &[(&str, &str, &str)] = &[
+    ("clear", "", "Start a new conversation"),
+    ("system", " <text>", "Set the system message"),
+    ("model", " <name>", "Switch model, keeping the conversation"),
+    (
+        "edit",
+        " [text]",
+        "Write a message in $EDITOR, then send it",
+    ),
+    ("save", " <path>", "Save every branch of the conversation"),
+    ("load", " <path>", "Continue a saved conversation"),
+    ("undo", "", "Remove the last prompt and its reply"),
+    ("retry", "", "Ask for a new reply to the last prompt"),
+    ("tokens", "", "Show the tokens used so far"),
+    ("history", "", "Show the conversation"),
+    ("help", "", "Show this list"),
+];
+
+/// A line entered at the REPL prompt starting with `/`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SlashCommand {
+    Clear,
+    System(String),
+    Model(String),
+    Edit(String),
+    Save(PathBuf),
+    Load(PathBuf),
+    Undo,
+    Retry,
+    Tokens,
+    History,
+    Help,
+}
+
+impl SlashCommand {
+    /// Parse a line entered at the prompt, or `None` if it is a message for
+    /// the model. A leading `//` sends the rest of the line, starting with a
+    /// single `/`, as a message.
+    pub fn parse(line: &str) -> Option<Result<Self, String>> {
+        let command = line.strip_prefix('/')?;
+        if command.starts_with('/') {
+            return None;
+        }
+        Some(command.parse())
+    }
+
+    /// Help text listing every command.
+    pub fn help() -> String {
+        let mut help: Vec<String> = COMMANDS
+            .iter()
+            .map(|(name, args, about)| format!("  {:<16}{about}", format!("/{name}{args}")))
+            .collect();
+        help.push("Start a message with // to send it beginning with /".to_string());
+        help.push("End a line with \\ or open a ``` block to continue on the next".to_string());
+        help.join("\n")
+    }
+}
+
+impl FromStr for SlashCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, argument) = match s.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (s, ""),
+        };
+        let required = |argument: &str| match argument {
+            "" => {
+                let (_, args, _) = COMMANDS.iter().find(|(n, _, _)| *n == name).unwrap();
+                Err(format!("usage: /{name}{args}"))
+            }
+            argument => Ok(argument.to_string()),
+        };
+        match name {
+            "clear" => Ok(Self::Clear),
+            "system" => required(argument).map(Self::System),
+            "model" => required(argument).map(Self::Model),
+            "edit" => Ok(Self::Edit(argument.to_string())),
+            "save" => required(argument).map(|path| Self::Save(path.into())),
+            "load" => required(argument).map(|path| Self::Load(path.into())),
+            "undo" => Ok(Self::Undo),
+            "retry" => Ok(Self::Retry),
+            "tokens" => Ok(Self::Tokens),
+            "history" => Ok(Self::History),
+            "help" => Ok(Self::Help),
+            _ => Err(format!("unknown command /{name}; try /help")),
+        }
+    }
+}
+
+/// Whether `input` continues on another line: its last line ends with `\`
+/// or a ``` block is left open.
+fn is_incomplete(input: &str) -> bool {
+    let fences = input
+        .lines()
+        .filter(|line| line.trim_start().starts_with("```"))
+        .count();
+    fences % 2 == 1 || input.ends_with('\\')
+}
+
+/// The message entered as `input`, dropping the `\` ending each continued
+/// line outside of code blocks.
+pub fn join_lines(input: &str) -> String {
+    let mut in_code = false;
+    let lines: Vec<&str> = input
+        .split('\n')
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_code = !in_code;
+            }
+            match in_code {
+                true => line,
+                false => line.strip_suffix('\\').unwrap_or(line),
+            }
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Open `$VISUAL` or `$EDITOR` (by default `vi`) on a temporary file holding
+/// `draft`, returning the file's contents once the editor exits.
+pub fn open_editor(draft: &str) -> anyhow::Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // the editor may be given with arguments, e.g. `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+
+    let file = tempfile::Builder::new()
+        .prefix("air-")
+        .suffix(".md")
+        .tempfile()?;
+    std::fs::write(file.path(), draft)?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(file.path())
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("{editor} exited with {status}");
+    }
+    Ok(std::fs::read_to_string(file.path())?)
+}
+
+/// Line editor support for the REPL: completes command names and paths
+/// after `/save` and `/load`, and continues unfinished input on a new line.
+#[derive(Default)]
+pub struct ReplHelper {
+    filenames: FilenameCompleter,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let Some(typed) = line[..pos].strip_prefix('/') else {
+            return Ok((0, Vec::new()));
+        };
+        if typed.starts_with("save ") || typed.starts_with("load ") {
+            return self.filenames.complete_path(line, pos);
+        }
+        if typed.contains(char::is_whitespace) {
+            return Ok((0, Vec::new()));
+        }
+        let candidates = COMMANDS
+            .iter()
+            .filter(|(name, _, _)| name.starts_with(typed))
+            .map(|(name, args, _)| Pair {
+                display: format!("/{name}{args}"),
+                replacement: match args.is_empty() {
+                    true => format!("/{name}"),
+                    false => format!("/{name} "),
+                },
+            })
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(match is_incomplete(ctx.input()) {
+            true => ValidationResult::Incomplete,
+            false => ValidationResult::Valid(None),
+        })
+    }
+}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::history::DefaultHistory;
+
+    #[test]
+    fn test_parse_slash_commands() {
+        assert_eq!(SlashCommand::parse("Hello"), None);
+        assert_eq!(SlashCommand::parse("//etc/hosts?"), None);
+        assert_eq!(SlashCommand::parse("/undo"), Some(Ok(SlashCommand::Undo)));
+        assert_eq!(
+            SlashCommand::parse("/system  Be brief. "),
+            Some(Ok(SlashCommand::System("Be brief.".to_string())))
+        );
+        assert_eq!(
+            SlashCommand::parse("/save"),
+            Some(Err("usage: /save <path>".to_string()))
+        );
+        assert!(matches!(SlashCommand::parse("/frobnicate"), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_multi_line_input() {
+        assert!(is_incomplete("first \\"));
+        assert!(is_incomplete("Explain:\n```rust\nfn main() {}"));
+        assert!(!is_incomplete("Explain:\n```rust\nfn main() {}\n```"));
+
+        assert_eq!(join_lines("first \\\nsecond"), "first \nsecond");
+        let code = "Explain:\n```c\n#define ONE \\\n  1\n```";
+        assert_eq!(join_lines(code), code);
+    }
+
+    #[test]
+    fn test_complete_command_names() {
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let (start, candidates) = ReplHelper::default().complete("/s", 2, &ctx).unwrap();
+        let replacements: Vec<_> = candidates.into_iter().map(|c| c.replacement).collect();
+        assert_eq!(start, 0);
+        assert_eq!(replacements, vec!["/system ", "/save "]);
+
+        let (_, candidates) = ReplHelper::default().complete("hi /s", 5, &ctx).unwrap();
+        assert!(candidates.is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Directory holding air's configuration: `$XDG_CONFIG_HOME/air`, by
+/// default `~/.config/air`.
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Directory holding air's state, such as input history:
+/// `$XDG_STATE_HOME/air`, by default `~/.local/state/air`.
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// Directory holding air's caches, such as model lists:
+/// `$XDG_CACHE_HOME/air`, by default `~/.cache/air`.
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+fn xdg_dir(variable: &str, fallback: &str) -> Option<PathBuf> {
+    let base = std::env::var_os(variable)
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))?;
+    Some(base.join("air"))
+}
+
+/// Read a TOML file into `T`, or its default if the file does not exist.
+pub fn read<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => from_toml(&text),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Write `value` to a TOML file, creating its directory if needed. Files
+/// that may hold secrets are made readable by their owner only, even if they
+/// already existed with wider permissions.
+pub fn write<T: Serialize>(path: &Path, value: &T, private: bool) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    std::io::Write::write_all(&mut file, to_toml(value)?.as_bytes())?;
+    Ok(())
+}
+
+/// Parse TOML text into `T`.
+pub fn from_toml<T: DeserializeOwned>(text: &str) -> Result<T> {
+    Ok(toml_edit::de::from_str(text)?)
+}
+
+/// Format `value` as TOML, writing nested maps as tables. Unset options
+/// are omitted, as TOML has no null value.
+pub fn to_toml<T: Serialize>(value: &T) -> Result<String> {
+    Ok(toml_edit::ser::to_string_pretty(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Settings {
+        name: String,
+        profiles: BTreeMap<String, Entry>,
+        headers: Vec<Header>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        url: Option<String>,
+        stop: Vec<String>,
+        temperature: f64,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Header {
+        name: String,
+        value: String,
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let entry = |url: Option<&str>, temperature| Entry {
+            url: url.map(String::from),
+            stop: vec!["END".to_string()],
+            temperature,
+        };
+        let settings = Settings {
+            name: "work".to_string(),
+            profiles: BTreeMap::from([
+                ("home".to_string(), entry(None, f64::INFINITY)),
+                (
+                    "work".to_string(),
+                    entry(Some("http://localhost:4000/v1"), 0.5),
+                ),
+            ]),
+            headers: vec![Header {
+                name: "X-Extra".to_string(),
+                value: "yes".to_string(),
+            }],
+        };
+        let text = to_toml(&settings).unwrap();
+        assert!(text.contains("[profiles.work]"), "{text}");
+        assert!(text.contains("temperature = inf"), "{text}");
+        assert_eq!(text.matches("\nurl =").count(), 1, "{text}");
+        assert!(text.contains("[[headers]]"), "{text}");
+        assert_eq!(from_toml::<Settings>(&text).unwrap(), settings);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private_narrows_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.toml");
+        std::fs::write(&path, "").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        write(&path, &Settings::default(), true).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_read_missing_file_is_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let value: Settings = read(&dir.path().join("missing.toml")).unwrap();
+        assert_eq!(value, Settings::default());
+    }
+}
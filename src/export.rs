@@ -0,0 +1,372 @@
+use std::{fmt::Display, fmt::Write, str::FromStr, time::Duration};
+
+use crate::transcript::Entry;
+use crate::{Message, Role};
+
+/// Document formats a conversation can be rendered to for sharing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+
+    /// A self-contained page with inline CSS
+    Html,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            "html" => Ok(ExportFormat::Html),
+            _ => Err(format!("unknown export format: {s}")),
+        }
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportFormat::Markdown => write!(f, "markdown"),
+            ExportFormat::Html => write!(f, "html"),
+        }
+    }
+}
+
+/// Totals over the responses in a transcript, from their recorded metadata.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsageSummary {
+    /// Responses with metadata recorded
+    pub responses: usize,
+
+    /// Tokens used across those responses, if every one reported usage
+    pub total_tokens: Option<u64>,
+
+    /// Time spent waiting on responses
+    pub latency: Duration,
+
+    /// Providers that responded, in order of first appearance
+    pub providers: Vec<String>,
+}
+
+impl UsageSummary {
+    pub fn from_entries(entries: &[Entry]) -> Self {
+        let mut summary = UsageSummary {
+            total_tokens: Some(0),
+            ..Default::default()
+        };
+        let responses = entries
+            .iter()
+            .filter(|entry| entry.message.role == Role::Assistant)
+            .filter_map(|entry| entry.metadata.as_ref());
+        for metadata in responses {
+            summary.responses += 1;
+            let tokens = metadata.usage.as_ref().and_then(|usage| usage.total_tokens);
+            summary.total_tokens = summary.total_tokens.zip(tokens).map(|(x, y)| x + y);
+            summary.latency += metadata.latency.unwrap_or_default();
+            if let Some(provider) = &metadata.provider {
+                if !summary.providers.contains(provider) {
+                    summary.providers.push(provider.clone());
+                }
+            }
+        }
+        summary
+    }
+}
+
+impl Display for UsageSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plural = if self.responses == 1 { "" } else { "s" };
+        write!(f, "{} response{plural}", self.responses)?;
+        if let Some(tokens) = self.total_tokens {
+            write!(f, ", {tokens} tokens")?;
+        }
+        write!(f, ", {:.1}s waiting", self.latency.as_secs_f64())?;
+        if !self.providers.is_empty() {
+            write!(f, " ({})", self.providers.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Render a conversation as a document with a heading per message, followed
+/// by the usage summary if given.
+///
+/// # Examples
+///
+/// ```
+/// use air::export::{render, ExportFormat};
+/// use air::Message;
+///
+/// let messages = [Message::user("Hi"), Message::assistant("Hello!")];
+/// let document = render(&messages, ExportFormat::Markdown, None);
+/// assert!(document.starts_with("## User\n\nHi\n"));
+/// ```
+pub fn render(messages: &[Message], format: ExportFormat, usage: Option<&UsageSummary>) -> String {
+    match format {
+        ExportFormat::Markdown => markdown(messages, usage),
+        ExportFormat::Html => html(messages, usage),
+    }
+}
+
+/// A run of message content: prose, or a fenced code block.
+#[derive(Debug, PartialEq)]
+enum Block<'a> {
+    Text(String),
+    Code { lang: &'a str, code: String },
+}
+
+/// Split content into prose and fenced code blocks. A fence left open runs
+/// to the end of the content.
+fn blocks(content: &str) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut text = String::new();
+    let mut code: Option<(&str, String)> = None;
+    for line in content.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        match (&mut code, fence) {
+            (None, Some(lang)) => {
+                if !text.trim().is_empty() {
+                    blocks.push(Block::Text(std::mem::take(&mut text)));
+                }
+                // drop any blank lines before the fence
+                text.clear();
+                code = Some((lang.trim(), String::new()));
+            }
+            (Some(_), Some(rest)) if rest.trim().is_empty() => {
+                let (lang, code) = code.take().unwrap();
+                blocks.push(Block::Code { lang, code });
+            }
+            (Some((_, buffer)), _) => {
+                buffer.push_str(line);
+                buffer.push('\n');
+            }
+            (None, None) => {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+    }
+    match code {
+        Some((lang, code)) => blocks.push(Block::Code { lang, code }),
+        None if !text.trim().is_empty() => blocks.push(Block::Text(text)),
+        None => {}
+    }
+    blocks
+}
+
+fn markdown(messages: &[Message], usage: Option<&UsageSummary>) -> String {
+    let mut document = String::new();
+    for message in messages {
+        let _ = writeln!(document, "## {}\n", message.role);
+        for block in blocks(&message.content) {
+            let _ = match block {
+                Block::Text(text) => writeln!(document, "{}\n", text.trim_end()),
+                Block::Code { lang, code } => writeln!(document, "```{lang}\n{code}```\n"),
+            };
+        }
+        for call in &message.tool_calls {
+            let _ = writeln!(
+                document,
+                "> Called `{}` with `{}`\n",
+                call.name, call.arguments
+            );
+        }
+    }
+    if let Some(usage) = usage {
+        let _ = writeln!(document, "---\n\n*{usage}*");
+    }
+    document
+}
+
+const STYLE: &str = "\
+body { font-family: system-ui, sans-serif; background: #f6f7f9; color: #1f2328; margin: 0; }
+main { max-width: 48rem; margin: 2rem auto; padding: 0 1rem; }
+section { background: #fff; border-radius: 8px; padding: 0.5rem 1.25rem; margin-bottom: 1rem; \
+box-shadow: 0 1px 2px rgba(0, 0, 0, 0.08); }
+section.user { border-left: 4px solid #0969da; }
+section.assistant { border-left: 4px solid #1a7f37; }
+section.system, section.tool { border-left: 4px solid #8c959f; }
+h2 { font-size: 0.85rem; text-transform: uppercase; letter-spacing: 0.05em; color: #57606a; }
+pre { background: #f0f2f4; padding: 0.75rem; border-radius: 6px; overflow-x: auto; }
+code { font-family: ui-monospace, monospace; font-size: 0.9em; }
+footer { color: #57606a; font-size: 0.85rem; text-align: center; }
+";
+
+fn html(messages: &[Message], usage: Option<&UsageSummary>) -> String {
+    let mut document = String::new();
+    let _ = write!(
+        document,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Conversation</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n<main>\n"
+    );
+    for message in messages {
+        let role = message.role.to_string();
+        let _ = writeln!(
+            document,
+            "<section class=\"{}\">\n<h2>{role}</h2>",
+            role.to_lowercase()
+        );
+        for block in blocks(&message.content) {
+            match block {
+                Block::Text(text) => {
+                    for paragraph in text.split("\n\n").filter(|p| !p.trim().is_empty()) {
+                        let _ = writeln!(document, "<p>{}</p>", inline(paragraph.trim()));
+                    }
+                }
+                Block::Code { lang, code } => {
+                    let class = match lang {
+                        "" => String::new(),
+                        lang => format!(" class=\"language-{}\"", escape(lang)),
+                    };
+                    let _ = writeln!(document, "<pre><code{class}>{}</code></pre>", escape(&code));
+                }
+            }
+        }
+        for call in &message.tool_calls {
+            let _ = writeln!(
+                document,
+                "<p><em>Called <code>{}</code> with <code>{}</code></em></p>",
+                escape(&call.name),
+                escape(&call.arguments)
+            );
+        }
+        document.push_str("</section>\n");
+    }
+    if let Some(usage) = usage {
+        let _ = writeln!(document, "<footer>{}</footer>", escape(&usage.to_string()));
+    }
+    document.push_str("</main>\n</body>\n</html>\n");
+    document
+}
+
+/// Escape text for inclusion in HTML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape a paragraph of prose, rendering `inline code` and line breaks.
+fn inline(paragraph: &str) -> String {
+    let escaped = escape(paragraph);
+    // an unpaired backtick is literal text rather than the start of code
+    if escaped.matches('`').count() % 2 == 1 {
+        return escaped.replace('\n', "<br>\n");
+    }
+
+    let mut rendered = String::with_capacity(escaped.len());
+    for (i, span) in escaped.split('`').enumerate() {
+        // odd spans sit between a pair of backticks
+        match i % 2 {
+            1 => {
+                let _ = write!(rendered, "<code>{span}</code>");
+            }
+            _ => rendered.push_str(span),
+        }
+    }
+    rendered.replace('\n', "<br>\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::Metadata;
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::user("How do I print in Rust?"),
+            Message::assistant("Use `println!`:\n\n```rust\nprintln!(\"<hi>\");\n```\nThat's all."),
+        ]
+    }
+
+    #[test]
+    fn test_blocks_split_fences() {
+        let content = "Before\n```sh\nls -l\n```\nAfter\n```\nunclosed";
+        assert_eq!(
+            blocks(content),
+            vec![
+                Block::Text("Before\n".to_string()),
+                Block::Code {
+                    lang: "sh",
+                    code: "ls -l\n".to_string()
+                },
+                Block::Text("After\n".to_string()),
+                Block::Code {
+                    lang: "",
+                    code: "unclosed\n".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let document = render(&conversation(), ExportFormat::Markdown, None);
+        assert_eq!(
+            document,
+            "## User\n\nHow do I print in Rust?\n\n## Assistant\n\nUse `println!`:\n\n\
+             ```rust\nprintln!(\"<hi>\");\n```\n\nThat's all.\n\n"
+        );
+
+        let unclosed = [Message::assistant("```py\nprint(1)")];
+        let document = render(&unclosed, ExportFormat::Markdown, None);
+        assert!(document.ends_with("```py\nprint(1)\n```\n\n"));
+    }
+
+    #[test]
+    fn test_render_html() {
+        let document = render(&conversation(), ExportFormat::Html, None);
+        assert!(document.starts_with("<!DOCTYPE html>"));
+        assert!(document.contains("<style>"));
+        assert!(document.contains("<section class=\"assistant\">\n<h2>Assistant</h2>"));
+        assert!(document.contains("<p>Use <code>println!</code>:</p>"));
+        assert!(document.contains(
+            "<pre><code class=\"language-rust\">println!(&quot;&lt;hi&gt;&quot;);\n</code></pre>"
+        ));
+        assert!(document.contains("<p>That&#39;s all.</p>"));
+        assert!(!document.contains("<footer>"));
+    }
+
+    #[test]
+    fn test_usage_summary() {
+        let metadata = |tokens: u64, millis: u64| Metadata {
+            provider: Some("OpenAI gpt-4".to_string()),
+            usage: serde_json::from_value(serde_json::json!({ "total_tokens": tokens })).ok(),
+            latency: Some(Duration::from_millis(millis)),
+            ..Default::default()
+        };
+        let entries: Vec<Entry> = conversation()
+            .into_iter()
+            .chain([Message::assistant("Anything else?")])
+            .enumerate()
+            .map(|(i, message)| Entry {
+                metadata: (message.role == Role::Assistant).then(|| metadata(10 * i as u64, 500)),
+                message,
+            })
+            .collect();
+
+        let summary = UsageSummary::from_entries(&entries);
+        assert_eq!(summary.responses, 2);
+        assert_eq!(summary.total_tokens, Some(30));
+        assert_eq!(
+            summary.to_string(),
+            "2 responses, 30 tokens, 1.0s waiting (OpenAI gpt-4)"
+        );
+
+        let document = render(&conversation(), ExportFormat::Markdown, Some(&summary));
+        assert!(
+            document.ends_with("---\n\n*2 responses, 30 tokens, 1.0s waiting (OpenAI gpt-4)*\n")
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use super::{reject_parameters, reject_tools, CheckStatus, Usage};
+use crate::{Completion, Message, Provider, ProviderError, Request, Role};
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// A provider that sends messages to the Anthropic Messages API.
+pub struct Anthropic {
+    name: String,
+    key: String,
+    base_url: String,
+    max_tokens: u32,
+}
+
+impl Anthropic {
+    const BASE_URL: &'static str = "https://api.anthropic.com/v1";
+    const VERSION: &'static str = "2023-06-01";
+    const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+    pub fn new<S: Into<String>>(name: S, key: S) -> Self {
+        Self {
+            name: name.into(),
+            key: key.into(),
+            base_url: Self::BASE_URL.to_string(),
+            max_tokens: Self::DEFAULT_MAX_TOKENS,
+        }
+    }
+
+    /// Use an alternative API root (e.g. a proxy), such as `http://localhost:8080/v1`.
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Upper bound on generated tokens, which the Messages API requires.
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Generation parameters the Messages API accepts.
+    const PARAMETERS: &'static [&'static str] =
+        &["temperature", "top_p", "max_completion_tokens", "stop"];
+
+    /// Build the request body, hoisting system messages out of the message
+    /// list into the top-level `system` prompt as the API requires.
+    fn payload(&self, request: &Request) -> serde_json::Value {
+        let (system, messages): (Vec<&Message>, Vec<&Message>) = request
+            .messages
+            .iter()
+            .partition(|m| m.role == Role::System);
+
+        let parameters = request.parameters;
+        let mut payload = serde_json::json!({
+            "model": self.name,
+            "max_tokens": parameters.max_completion_tokens.unwrap_or(self.max_tokens),
+            "messages": messages,
+        });
+        if let Some(temperature) = parameters.temperature {
+            payload["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(top_p) = parameters.top_p {
+            payload["top_p"] = serde_json::json!(top_p);
+        }
+        if !parameters.stop.is_empty() {
+            payload["stop_sequences"] = serde_json::json!(parameters.stop);
+        }
+        if !system.is_empty() {
+            let prompt = system
+                .iter()
+                .map(|m| m.content.as_str())
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            payload["system"] = serde_json::Value::String(prompt);
+        }
+        payload
+    }
+}
+
+impl Display for Anthropic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Anthropic {}", self.name)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: MessagesUsage,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ContentBlock {
+    // Block kind; only `text` blocks carry assistant text
+    #[serde(rename = "type")]
+    kind: String,
+
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct MessagesUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl From<MessagesUsage> for Usage {
+    fn from(value: MessagesUsage) -> Self {
+        Self {
+            prompt_tokens: Some(value.input_tokens),
+            completion_tokens: Some(value.output_tokens),
+            total_tokens: Some(value.input_tokens + value.output_tokens),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ModelEndpointResponse {
+    data: Vec<ModelEndpointEntity>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ModelEndpointEntity {
+    id: String,
+}
+
+impl Provider for Anthropic {
+    fn send(
+        &self,
+        request: &Request,
+        client: &reqwest::blocking::Client,
+    ) -> Result<Completion, ProviderError> {
+        reject_tools(self, request)?;
+        reject_parameters(self, request, Self::PARAMETERS)?;
+        let response = client
+            .post(format!("{}/messages", self.base_url))
+            .header("x-api-key", &self.key)
+            .header("anthropic-version", Self::VERSION)
+            .json(&self.payload(request))
+            .send()?
+            .check()?
+            .json::<MessagesResponse>()?;
+
+        let text = response
+            .content
+            .into_iter()
+            .filter(|block| block.kind == "text")
+            .map(|block| block.text)
+            .collect::<String>();
+        if text.is_empty() && response.stop_reason.is_none() {
+            return Err(ProviderError::EmptyResponse);
+        }
+
+        Ok(Completion {
+            message: Message::assistant(text),
+            usage: response.usage.into(),
+            finish_reason: response.stop_reason,
+        })
+    }
+
+    fn models(&self, client: &reqwest::blocking::Client) -> Result<Vec<String>, ProviderError> {
+        let response = client
+            .get(format!("{}/models", self.base_url))
+            .header("x-api-key", &self.key)
+            .header("anthropic-version", Self::VERSION)
+            .send()?
+            .check()?
+            .json::<ModelEndpointResponse>()?;
+
+        Ok(response.data.into_iter().map(|model| model.id).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MockResponse, MockServer};
+    use crate::Parameters;
+    use serde_json::json;
+
+    fn reply() -> MockResponse {
+        MockResponse::json(
+            200,
+            json!({
+                "id": "msg_01",
+                "type": "message",
+                "role": "assistant",
+                "content": [{"type": "text", "text": "Hello, user!"}],
+                "model": "claude-3-haiku-20240307",
+                "stop_reason": "end_turn",
+                "usage": {"input_tokens": 12, "output_tokens": 4}
+            }),
+        )
+    }
+
+    #[test]
+    fn test_anthropic_send() {
+        let server = MockServer::start(vec![reply()]);
+        let provider = Anthropic::new("claude-3-haiku-20240307", "secret")
+            .with_base_url(server.url())
+            .with_max_tokens(256);
+        let context = vec![
+            Message::system("Be brief."),
+            Message::user("Hello, assistant!"),
+        ];
+
+        let client = reqwest::blocking::Client::new();
+        let Completion { message, usage, .. } =
+            provider.send(&Request::new(&context), &client).unwrap();
+        assert_eq!(message, Message::assistant("Hello, user!"));
+        assert_eq!(usage.prompt_tokens, Some(12));
+        assert_eq!(usage.completion_tokens, Some(4));
+        assert_eq!(usage.total_tokens, Some(16));
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/messages");
+        assert_eq!(request.header("x-api-key"), Some("secret"));
+        assert_eq!(
+            request.header("anthropic-version"),
+            Some(Anthropic::VERSION)
+        );
+
+        let body = request.json();
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["system"], "Be brief.");
+        assert_eq!(
+            body["messages"],
+            json!([{"role": "user", "content": "Hello, assistant!"}])
+        );
+    }
+
+    #[test]
+    fn test_anthropic_parameters() {
+        let provider = Anthropic::new("claude-3-haiku-20240307", "secret");
+        let context = [Message::user("Hello, assistant!")];
+        let parameters = Parameters {
+            temperature: Some(0.5),
+            max_completion_tokens: Some(64),
+            stop: vec!["END".to_string()],
+            ..Default::default()
+        };
+        let payload = provider.payload(&Request::new(&context).with_parameters(&parameters));
+        assert_eq!(payload["temperature"], 0.5);
+        assert_eq!(payload["max_tokens"], 64);
+        assert_eq!(payload["stop_sequences"], json!(["END"]));
+
+        // rejected before any request is made
+        let parameters = Parameters {
+            seed: Some(7),
+            ..Default::default()
+        };
+        let request = Request::new(&context).with_parameters(&parameters);
+        let client = reqwest::blocking::Client::new();
+        match provider.send(&request, &client) {
+            Err(ProviderError::Unsupported(detail)) => assert!(detail.ends_with("seed")),
+            _ => panic!("Expected the seed to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_anthropic_http_error() {
+        let body = json!({
+            "type": "error",
+            "error": {"type": "authentication_error", "message": "invalid x-api-key"}
+        });
+        let server = MockServer::start(vec![MockResponse::json(401, body)]);
+        let provider = Anthropic::new("claude-3-haiku-20240307", "bad").with_base_url(server.url());
+
+        let client = reqwest::blocking::Client::new();
+        match provider.send(&Request::new(&[Message::user("Hi")]), &client) {
+            Err(ProviderError::AuthenticationFailed { error: Some(error) }) => {
+                assert_eq!(error.message, "invalid x-api-key");
+                assert_eq!(error.kind.as_deref(), Some("authentication_error"));
+            }
+            _ => panic!("Expected 401 Unauthorized error"),
+        }
+    }
+
+    #[test]
+    fn test_anthropic_models() {
+        let body = json!({"data": [{"id": "claude-3-opus-20240229", "type": "model"}]});
+        let server = MockServer::start(vec![MockResponse::json(200, body)]);
+        let provider =
+            Anthropic::new("claude-3-opus-20240229", "secret").with_base_url(server.url());
+
+        let client = reqwest::blocking::Client::new();
+        let models = provider.models(&client).unwrap();
+        assert_eq!(models, vec!["claude-3-opus-20240229"]);
+        assert_eq!(server.requests()[0].method, "GET");
+    }
+}
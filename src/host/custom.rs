@@ -1,17 +1,421 @@
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::fmt::Display;
+use std::time::Duration;
 use url::Url;
 
-use super::Usage;
-use crate::{Message, Provider, ProviderError};
+use super::{reject_parameters, reject_tools, CheckStatus, Usage};
+use crate::{Completion, Message, Provider, ProviderError, Request, Role};
+#[cfg(feature = "async")]
+use {super::check_async, crate::AsyncProvider};
+
+/// Generation parameters a request body template may place.
+const PARAMETERS: &[&str] = &[
+    "temperature",
+    "top_p",
+    "max_completion_tokens",
+    "stop",
+    "seed",
+    "presence_penalty",
+    "frequency_penalty",
+    "response_format",
+];
+
+/// Values a request body template may place besides the parameters.
+const VALUES: &[&str] = &["messages", "model", "prompt", "system"];
+
+/// How to talk to an HTTP backend unlike any other host: a template for the
+/// request body, and JSON pointers locating the reply in the response.
+///
+/// A string in `body` made of a single placeholder is replaced by its value:
+/// `{{messages}}`, `{{model}}`, `{{prompt}}` (the last user message),
+/// `{{system}}` or a generation parameter such as `{{temperature}}`. Fields
+/// whose placeholder has no value are left out, and parameters without a
+/// placeholder are refused.
+///
+/// # Examples
+///
+/// ```
+/// use air::host::CustomSpec;
+///
+/// let spec: CustomSpec = serde_json::from_value(serde_json::json!({
+///     "body": {"model": "{{model}}", "input": "{{prompt}}", "temperature": "{{temperature}}"},
+///     "content": "/output/0/text",
+///     "usage": {"prompt_tokens": "/usage/input", "completion_tokens": "/usage/output"}
+/// }))
+/// .unwrap();
+/// assert!(spec.check().is_ok());
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CustomSpec {
+    /// Template of the request body
+    pub body: Value,
+
+    /// Pointer to the reply's text in the response
+    pub content: String,
+
+    /// Pointer to why the model stopped generating, if reported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+
+    /// Pointers to the token counts, if reported
+    #[serde(default)]
+    pub usage: UsagePointers,
+
+    /// Where the host's models are listed, if anywhere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub models: Option<ModelSource>,
+}
+
+/// How a custom host's models are found: a fixed list, or an endpoint
+/// answering `GET` with a JSON listing.
+///
+/// # Examples
+///
+/// ```
+/// use air::host::ModelSource;
+///
+/// let listed: ModelSource = serde_json::from_value(serde_json::json!(["small", "large"])).unwrap();
+/// let fetched: ModelSource = serde_json::from_value(serde_json::json!({
+///     "url": "/v1/models",
+///     "list": "/data",
+///     "name": "/id"
+/// }))
+/// .unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ModelSource {
+    List(Vec<String>),
+
+    Endpoint {
+        /// Address of the listing, absolute or relative to the host's URL
+        url: String,
+
+        /// Pointer to the array of models in the response; by default the
+        /// response itself
+        #[serde(default)]
+        list: String,
+
+        /// Pointer to the name within each model; by default the model is
+        /// its name
+        #[serde(default)]
+        name: String,
+    },
+}
+
+impl ModelSource {
+    /// Model names in a listing fetched from the endpoint.
+    fn names(&self, body: &str) -> Result<Vec<String>, ProviderError> {
+        let ModelSource::Endpoint { list, name, .. } = self else {
+            return Ok(Vec::new());
+        };
+        let response: Value = serde_json::from_str(body)?;
+        let Some(Value::Array(models)) = response.pointer(list) else {
+            return Err(ProviderError::EmptyResponse);
+        };
+        Ok(models
+            .iter()
+            .filter_map(|model| model.pointer(name)?.as_str().map(str::to_string))
+            .collect())
+    }
+}
+
+/// JSON pointers to the token counts in a response, see `CustomSpec`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsagePointers {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<String>,
+
+    /// Pointer to the total; by default the sum of the other two
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_tokens: Option<String>,
+}
+
+impl CustomSpec {
+    /// Check that every placeholder is known and every pointer well formed,
+    /// describing the first problem found.
+    pub fn check(&self) -> Result<(), String> {
+        let mut names = Vec::new();
+        placeholders(&self.body, &mut names);
+        if let Some(name) = names
+            .iter()
+            .find(|name| !VALUES.contains(name) && !PARAMETERS.contains(name))
+        {
+            return Err(format!(
+                "unknown placeholder {{{{{name}}}}} in the request body"
+            ));
+        }
+        let (list, name) = match &self.models {
+            Some(ModelSource::Endpoint { list, name, .. }) => (Some(list), Some(name)),
+            _ => (None, None),
+        };
+        let pointers = [
+            Some(&self.content),
+            self.finish_reason.as_ref(),
+            self.usage.prompt_tokens.as_ref(),
+            self.usage.completion_tokens.as_ref(),
+            self.usage.total_tokens.as_ref(),
+            list,
+            name,
+        ];
+        match pointers
+            .into_iter()
+            .flatten()
+            .find(|pointer| !pointer.is_empty() && !pointer.starts_with('/'))
+        {
+            Some(pointer) => Err(format!(
+                "{pointer} is not a JSON pointer, e.g. /data/0/text"
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Parameters the template places.
+    fn parameters(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        placeholders(&self.body, &mut names);
+        names.retain(|name| PARAMETERS.contains(name));
+        names
+    }
+
+    /// The request body for `request`, filling in the template.
+    fn body(&self, model: Option<&str>, request: &Request) -> Value {
+        let mut values = match serde_json::to_value(request.parameters) {
+            Ok(Value::Object(values)) => values,
+            _ => Map::new(),
+        };
+        let last = |role: Role| {
+            let message = request.messages.iter().rev().find(|m| m.role == role)?;
+            Some(Value::String(message.content.clone()))
+        };
+        values.insert("messages".to_string(), serde_json::json!(request.messages));
+        let optional = [
+            ("model", model.map(|model| Value::String(model.to_string()))),
+            ("prompt", last(Role::User)),
+            ("system", last(Role::System)),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                values.insert(name.to_string(), value);
+            }
+        }
+        fill(&self.body, &values).unwrap_or_default()
+    }
+
+    /// The completion found in a response body.
+    fn completion(&self, body: &str) -> Result<Completion, ProviderError> {
+        let response: Value = serde_json::from_str(body)?;
+        let content = match response.pointer(&self.content) {
+            None | Some(Value::Null) => return Err(ProviderError::EmptyResponse),
+            Some(content) => serde_json::from_value::<String>(content.clone())?,
+        };
+        let find = |pointer: &Option<String>| response.pointer(pointer.as_ref()?).cloned();
+        let count = |pointer: &Option<String>| find(pointer).and_then(|count| count.as_u64());
+        let (prompt_tokens, completion_tokens) = (
+            count(&self.usage.prompt_tokens),
+            count(&self.usage.completion_tokens),
+        );
+        let total_tokens = match &self.usage.total_tokens {
+            Some(_) => count(&self.usage.total_tokens),
+            None => prompt_tokens.zip(completion_tokens).map(|(p, c)| p + c),
+        };
+        Ok(Completion {
+            message: Message::assistant(content),
+            usage: Usage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens,
+            },
+            finish_reason: find(&self.finish_reason)
+                .and_then(|reason| reason.as_str().map(str::to_string)),
+        })
+    }
+}
+
+/// The placeholder a template string consists of, if any.
+fn placeholder(text: &str) -> Option<&str> {
+    Some(text.strip_prefix("{{")?.strip_suffix("}}")?.trim())
+}
+
+fn placeholders<'a>(template: &'a Value, names: &mut Vec<&'a str>) {
+    match template {
+        Value::String(text) => names.extend(placeholder(text)),
+        Value::Array(items) => items.iter().for_each(|item| placeholders(item, names)),
+        Value::Object(fields) => fields.values().for_each(|field| placeholders(field, names)),
+        _ => {}
+    }
+}
+
+/// Fill in the placeholders of `template`, or `None` if it is a placeholder
+/// without a value.
+fn fill(template: &Value, values: &Map<String, Value>) -> Option<Value> {
+    Some(match template {
+        Value::String(text) => match placeholder(text) {
+            Some(name) => values.get(name)?.clone(),
+            None => template.clone(),
+        },
+        Value::Array(items) => {
+            Value::Array(items.iter().filter_map(|item| fill(item, values)).collect())
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .filter_map(|(key, field)| Some((key.clone(), fill(field, values)?)))
+                .collect(),
+        ),
+        _ => template.clone(),
+    })
+}
 
 /// A custom provider that sends messages to a prescribed HTTP endpoint.
+/// Without a `CustomSpec` the bare messages are sent and the whole response
+/// body is taken as the reply.
+///
+/// # Examples
+///
+/// ```
+/// use air::host::Custom;
+/// use std::time::Duration;
+///
+/// let url = "http://localhost:8000/chat".parse().unwrap();
+/// let model = Custom::new(url)
+///     .with_bearer_auth("my-api-key")?
+///     .with_header("X-Request-Source", "air")?
+///     .with_timeout(Duration::from_secs(30));
+/// # Ok::<(), air::ProviderError>(())
+/// ```
 pub struct Custom {
     url: Url,
+    model: Option<String>,
+    spec: Option<CustomSpec>,
+    headers: HeaderMap,
+    timeout: Option<Duration>,
 }
 
 impl Custom {
     pub fn new(url: Url) -> Self {
-        Self { url }
+        Self {
+            url,
+            model: None,
+            spec: None,
+            headers: HeaderMap::new(),
+            timeout: None,
+        }
+    }
+
+    /// Name the model, for a spec placing `{{model}}`.
+    pub fn with_model<S: Into<String>>(mut self, name: S) -> Self {
+        self.model = Some(name.into());
+        self
+    }
+
+    /// Build requests and read responses as `spec` describes.
+    pub fn with_spec(mut self, spec: CustomSpec) -> Self {
+        self.spec = Some(spec);
+        self
+    }
+
+    /// Authenticate with `token` in a bearer `Authorization` header.
+    pub fn with_bearer_auth<S: Display>(self, token: S) -> Result<Self, ProviderError> {
+        self.with_header("Authorization", format!("Bearer {token}"))
+    }
+
+    /// Send an additional header with every request, replacing any of the
+    /// same name, e.g. the `Authorization` header set by `with_bearer_auth`.
+    /// Fails if the name or value cannot be sent in a header.
+    pub fn with_header<K: AsRef<str>, V: AsRef<str>>(
+        mut self,
+        name: K,
+        value: V,
+    ) -> Result<Self, ProviderError> {
+        let name = name.as_ref();
+        let (Ok(header), Ok(value)) = (
+            HeaderName::try_from(name),
+            HeaderValue::try_from(value.as_ref()),
+        ) else {
+            return Err(ProviderError::InvalidHeader(name.to_string()));
+        };
+        self.headers.insert(header, value);
+        Ok(self)
+    }
+
+    /// Give up on a request after `timeout` rather than waiting indefinitely.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Start a request to `url` with any headers and timeout applied.
+    fn request(
+        &self,
+        client: &reqwest::blocking::Client,
+        method: Method,
+        url: Url,
+    ) -> reqwest::blocking::RequestBuilder {
+        let request = client.request(method, url).headers(self.headers.clone());
+        match self.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        }
+    }
+
+    /// Where the spec says the models are listed.
+    fn model_source(&self) -> Result<&ModelSource, ProviderError> {
+        let source = self.spec.as_ref().and_then(|spec| spec.models.as_ref());
+        source.ok_or_else(|| {
+            ProviderError::Unsupported(format!("{self} lists no models; add `models` to its spec"))
+        })
+    }
+
+    /// Address of a model listing, resolved against the host's URL.
+    fn listing_url(&self, url: &str) -> Result<Url, ProviderError> {
+        self.url
+            .join(url)
+            .map_err(|err| ProviderError::UnknownError(format!("model listing at {url}: {err}")))
+    }
+
+    /// The request body: the spec's template filled in, or the bare messages.
+    fn payload(&self, request: &Request) -> Result<Value, ProviderError> {
+        reject_tools(self, request)?;
+        match &self.spec {
+            Some(spec) => {
+                reject_parameters(self, request, &spec.parameters())?;
+                Ok(spec.body(self.model.as_deref(), request))
+            }
+            None => {
+                reject_parameters(self, request, &[])?;
+                Ok(serde_json::json!(request.messages))
+            }
+        }
+    }
+
+    /// The completion in a response body, as located by the spec.
+    fn completion(&self, body: String) -> Result<Completion, ProviderError> {
+        match &self.spec {
+            Some(spec) => spec.completion(&body),
+            None => Ok(Completion::new(Message::assistant(body), Usage::new())),
+        }
+    }
+
+    /// Async counterpart of `request`.
+    #[cfg(feature = "async")]
+    fn request_async(
+        &self,
+        client: &reqwest::Client,
+        method: Method,
+        url: Url,
+    ) -> reqwest::RequestBuilder {
+        let request = client.request(method, url).headers(self.headers.clone());
+        match self.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        }
     }
 }
 
@@ -28,14 +432,223 @@ impl Display for Custom {
 impl Provider for Custom {
     fn send(
         &self,
-        context: &[Message],
+        request: &Request,
         client: &reqwest::blocking::Client,
-    ) -> Result<(Message, Usage), ProviderError> {
-        let response = client.post(self.url.as_str()).json(context).send()?;
-        Ok((Message::assistant(response.text()?), Usage::new()))
+    ) -> Result<Completion, ProviderError> {
+        let response = self
+            .request(client, Method::POST, self.url.clone())
+            .json(&self.payload(request)?)
+            .send()?
+            .check()?;
+        self.completion(response.text()?)
+    }
+
+    fn models(&self, client: &reqwest::blocking::Client) -> Result<Vec<String>, ProviderError> {
+        let source = self.model_source()?;
+        let url = match source {
+            ModelSource::List(names) => return Ok(names.clone()),
+            ModelSource::Endpoint { url, .. } => self.listing_url(url)?,
+        };
+        let response = self.request(client, Method::GET, url).send()?.check()?;
+        source.names(&response.text()?)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl AsyncProvider for Custom {
+    async fn send(
+        &self,
+        request: &Request<'_>,
+        client: &reqwest::Client,
+    ) -> Result<Completion, ProviderError> {
+        let response = self
+            .request_async(client, Method::POST, self.url.clone())
+            .json(&self.payload(request)?)
+            .send()
+            .await?;
+        self.completion(check_async(response).await?.text().await?)
+    }
+
+    async fn models(&self, client: &reqwest::Client) -> Result<Vec<String>, ProviderError> {
+        let source = self.model_source()?;
+        let url = match source {
+            ModelSource::List(names) => return Ok(names.clone()),
+            ModelSource::Endpoint { url, .. } => self.listing_url(url)?,
+        };
+        let response = self.request_async(client, Method::GET, url).send().await?;
+        source.names(&check_async(response).await?.text().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MockResponse, MockServer};
+    use serde_json::json;
+    use std::str::FromStr;
+
+    fn spec() -> CustomSpec {
+        serde_json::from_value(json!({
+            "body": {
+                "model": "{{model}}",
+                "input": {"history": "{{messages}}", "question": "{{prompt}}"},
+                "options": {"temperature": "{{temperature}}", "seed": "{{seed}}"},
+                "stream": false
+            },
+            "content": "/output/0/text",
+            "finish_reason": "/status",
+            "usage": {"prompt_tokens": "/usage/input", "completion_tokens": "/usage/output"}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_custom_send_with_headers() {
+        let server = MockServer::start(vec![MockResponse::new(200, "Hello, user!")]);
+        let provider = Custom::new(Url::from_str(server.url()).unwrap())
+            .with_bearer_auth("my-api-key")
+            .unwrap()
+            .with_header("X-Request-Source", "air")
+            .unwrap()
+            .with_timeout(Duration::from_secs(5));
+
+        let client = reqwest::blocking::Client::new();
+        let context = [Message::user("Hello, assistant!")];
+        let completion = Provider::send(&provider, &Request::new(&context), &client).unwrap();
+        assert_eq!(completion.message, Message::assistant("Hello, user!"));
+
+        let request = &server.requests()[0];
+        assert_eq!(request.header("authorization"), Some("Bearer my-api-key"));
+        assert_eq!(request.header("x-request-source"), Some("air"));
+    }
+
+    #[test]
+    fn test_custom_rejects_invalid_headers() {
+        let provider = || Custom::new(Url::from_str("http://localhost:8000").unwrap());
+        let err = provider().with_header("Bad Name", "air").err().unwrap();
+        assert!(matches!(err, ProviderError::InvalidHeader(ref name) if name == "Bad Name"));
+        assert!(provider().with_header("X-Source", "line\nbreak").is_err());
+        assert!(provider().with_bearer_auth("my-api-key").is_ok());
+    }
+
+    #[test]
+    fn test_custom_headers_replace() {
+        let server = MockServer::start(vec![MockResponse::new(200, "Hello, user!")]);
+        let provider = Custom::new(Url::from_str(server.url()).unwrap())
+            .with_bearer_auth("my-api-key")
+            .and_then(|provider| provider.with_header("X-Tenant", "docs"))
+            .and_then(|provider| provider.with_header("authorization", "Token other-key"))
+            .and_then(|provider| provider.with_header("x-tenant", "blog"))
+            .unwrap();
+
+        let client = reqwest::blocking::Client::new();
+        let context = [Message::user("Hello, assistant!")];
+        Provider::send(&provider, &Request::new(&context), &client).unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.header("authorization"), Some("Token other-key"));
+        assert_eq!(request.header("x-tenant"), Some("blog"));
+        let tenants = request
+            .headers
+            .iter()
+            .filter(|(name, _)| name == "x-tenant");
+        assert_eq!(tenants.count(), 1);
+    }
+
+    #[test]
+    fn test_custom_send_with_spec() {
+        let body = json!({
+            "output": [{"text": "Hello, user!"}],
+            "status": "complete",
+            "usage": {"input": 12, "output": 4}
+        });
+        let server = MockServer::start(vec![MockResponse::json(200, body)]);
+        let provider = Custom::new(Url::from_str(server.url()).unwrap())
+            .with_model("house-model")
+            .with_spec(spec());
+
+        let client = reqwest::blocking::Client::new();
+        let context = [Message::user("Hello, assistant!")];
+        let parameters = crate::Parameters {
+            temperature: Some(0.5),
+            ..Default::default()
+        };
+        let request = Request::new(&context).with_parameters(&parameters);
+        let completion = Provider::send(&provider, &request, &client).unwrap();
+        assert_eq!(completion.message, Message::assistant("Hello, user!"));
+        assert_eq!(completion.finish_reason.as_deref(), Some("complete"));
+        assert_eq!(completion.usage.total_tokens, Some(16));
+
+        let sent = server.requests()[0].json();
+        assert_eq!(sent["model"], "house-model");
+        assert_eq!(sent["input"]["question"], "Hello, assistant!");
+        assert_eq!(sent["input"]["history"][0]["content"], "Hello, assistant!");
+        assert_eq!(sent["options"], json!({"temperature": 0.5}));
+        assert_eq!(sent["stream"], false);
+    }
+
+    #[test]
+    fn test_custom_spec_refuses_unplaced_parameters() {
+        let provider =
+            Custom::new(Url::from_str("http://localhost:8000").unwrap()).with_spec(spec());
+        let context = [Message::user("Hello, assistant!")];
+        let parameters = crate::Parameters {
+            top_p: Some(0.9),
+            ..Default::default()
+        };
+        let request = Request::new(&context).with_parameters(&parameters);
+        assert!(matches!(
+            provider.payload(&request),
+            Err(ProviderError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_custom_spec_check() {
+        assert!(spec().check().is_ok());
+
+        let mut typo = spec();
+        typo.body["options"]["temperature"] = json!("{{temprature}}");
+        assert_eq!(
+            typo.check().unwrap_err(),
+            "unknown placeholder {{temprature}} in the request body"
+        );
+
+        let mut path = spec();
+        path.content = "output.0.text".to_string();
+        assert!(path.check().is_err());
     }
 
-    fn models(&self, _client: &reqwest::blocking::Client) -> Result<Vec<String>, ProviderError> {
-        todo!("Implement this method")
+    #[test]
+    fn test_custom_models() {
+        let body = json!({"data": [{"id": "small"}, {"id": "large"}]});
+        let server = MockServer::start(vec![MockResponse::json(200, body)]);
+        let mut spec = spec();
+        spec.models = Some(ModelSource::Endpoint {
+            url: "/v1/models".to_string(),
+            list: "/data".to_string(),
+            name: "/id".to_string(),
+        });
+        let url = Url::from_str(server.url()).unwrap().join("/chat").unwrap();
+        let provider = Custom::new(url).with_spec(spec.clone());
+
+        let client = reqwest::blocking::Client::new();
+        let models = Provider::models(&provider, &client).unwrap();
+        assert_eq!(models, vec!["small", "large"]);
+        assert_eq!(server.requests()[0].path, "/v1/models");
+
+        spec.models = Some(ModelSource::List(vec!["house-model".to_string()]));
+        let provider = Custom::new(Url::from_str("http://localhost:8000").unwrap()).with_spec(spec);
+        assert_eq!(
+            Provider::models(&provider, &client).unwrap(),
+            vec!["house-model"]
+        );
+
+        let provider = Custom::new(Url::from_str("http://localhost:8000").unwrap());
+        assert!(matches!(
+            Provider::models(&provider, &client),
+            Err(ProviderError::Unsupported(_))
+        ));
     }
 }
@@ -0,0 +1,151 @@
+use super::{openai, CheckStatus};
+use crate::{Completion, DeltaStream, Provider, ProviderError, ProviderResponse, Request};
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use url::Url;
+
+/// A provider for a llama.cpp `server`, using its OpenAI-compatible
+/// `/v1/chat/completions` endpoint. The server hosts a single model, so the
+/// model name is optional and only forwarded when set.
+pub struct LlamaCpp {
+    name: Option<String>,
+    url: Url,
+}
+
+impl LlamaCpp {
+    /// Address the llama.cpp server listens on unless configured otherwise.
+    pub const DEFAULT_URL: &'static str = "http://localhost:8080";
+
+    pub fn new(url: Url) -> Self {
+        Self { name: None, url }
+    }
+
+    pub fn with_model<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/v1/{path}", self.url.as_str().trim_end_matches('/'))
+    }
+
+    fn payload(&self, request: &Request, stream: bool) -> serde_json::Value {
+        let mut payload = serde_json::json!({
+            "messages": request.messages,
+            "stream": stream,
+        });
+        if let Some(name) = &self.name {
+            payload["model"] = serde_json::Value::String(name.clone());
+        }
+        openai::apply_parameters(&mut payload, request.parameters);
+        // the server predates `max_completion_tokens`
+        if let Some(limit) = request.parameters.max_completion_tokens {
+            payload
+                .as_object_mut()
+                .unwrap()
+                .remove("max_completion_tokens");
+            payload["max_tokens"] = serde_json::json!(limit);
+        }
+        if !request.tools.is_empty() {
+            payload["tools"] = serde_json::json!(request.tools);
+        }
+        payload
+    }
+}
+
+impl Display for LlamaCpp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let location = self.url.host_str().unwrap_or("unknown location");
+        match &self.name {
+            Some(name) => write!(f, "llama.cpp {name} at {location}"),
+            None => write!(f, "llama.cpp model at {location}"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ModelEndpointResponse {
+    data: Vec<ModelEndpointEntity>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ModelEndpointEntity {
+    id: String,
+}
+
+impl Provider for LlamaCpp {
+    fn send(
+        &self,
+        request: &Request,
+        client: &reqwest::blocking::Client,
+    ) -> Result<Completion, ProviderError> {
+        let response = client
+            .post(self.endpoint("chat/completions"))
+            .json(&self.payload(request, false))
+            .send()?
+            .check()?
+            .json::<ProviderResponse>()?;
+
+        self.parse(response)
+    }
+
+    fn send_stream(
+        &self,
+        request: &Request,
+        client: &reqwest::blocking::Client,
+    ) -> Result<DeltaStream, ProviderError> {
+        let response = client
+            .post(self.endpoint("chat/completions"))
+            .json(&self.payload(request, true))
+            .send()?
+            .check()?;
+
+        Ok(openai::deltas(response))
+    }
+
+    fn models(&self, client: &reqwest::blocking::Client) -> Result<Vec<String>, ProviderError> {
+        let response = client
+            .get(self.endpoint("models"))
+            .send()?
+            .check()?
+            .json::<ModelEndpointResponse>()?;
+
+        Ok(response.data.into_iter().map(|model| model.id).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MockResponse, MockServer};
+    use crate::Message;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_llama_cpp_send() {
+        let body = json!({
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hello, user!"},
+                "finish_reason": "stop",
+                "logprobs": null
+            }],
+            "usage": {"prompt_tokens": 9, "completion_tokens": 4, "total_tokens": 13}
+        });
+        let server = MockServer::start(vec![MockResponse::json(200, body)]);
+        let provider = LlamaCpp::new(Url::from_str(server.url()).unwrap());
+
+        let client = reqwest::blocking::Client::new();
+        let Completion { message, usage, .. } = provider
+            .send(&Request::new(&[Message::user("Hi")]), &client)
+            .unwrap();
+        assert_eq!(message, Message::assistant("Hello, user!"));
+        assert_eq!(usage.total_tokens, Some(13));
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/v1/chat/completions");
+        assert!(request.json().get("model").is_none());
+    }
+}
@@ -1,10 +1,93 @@
+mod anthropic;
 mod custom;
+mod llama_cpp;
+mod ollama;
 mod openai;
-pub use custom::Custom;
+mod sse;
+pub use anthropic::Anthropic;
+pub use custom::{Custom, CustomSpec, ModelSource, UsagePointers};
+pub use llama_cpp::LlamaCpp;
+pub use ollama::Ollama;
 pub use openai::OpenAI;
 use serde::{Deserialize, Serialize};
+use std::{fmt::Display, time::Duration};
 
-#[derive(Serialize, Deserialize, Default)]
+use crate::{ProviderError, Request};
+use reqwest::{blocking, header::HeaderMap};
+
+/// Status checking for provider responses, in place of `error_for_status`,
+/// that keeps the provider's error details and any `Retry-After` hint.
+pub(crate) trait CheckStatus: Sized {
+    fn check(self) -> Result<Self, ProviderError>;
+}
+
+impl CheckStatus for blocking::Response {
+    fn check(self) -> Result<Self, ProviderError> {
+        let status = self.status();
+        if status.is_success() {
+            return Ok(self);
+        }
+        let retry_after = retry_after(self.headers());
+        let body = self.text().unwrap_or_default();
+        Err(ProviderError::from_response(status, retry_after, &body))
+    }
+}
+
+/// Async counterpart of `CheckStatus::check`.
+#[cfg(feature = "async")]
+pub(crate) async fn check_async(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, ProviderError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = retry_after(response.headers());
+    let body = response.text().await.unwrap_or_default();
+    Err(ProviderError::from_response(status, retry_after, &body))
+}
+
+/// Refuse a request offering tools to a host without tool calling, rather
+/// than letting the model answer as though none were offered.
+pub(crate) fn reject_tools(host: &dyn Display, request: &Request) -> Result<(), ProviderError> {
+    match request.tools.is_empty() {
+        true => Ok(()),
+        false => Err(ProviderError::Unsupported(format!(
+            "{host} does not support tool calling"
+        ))),
+    }
+}
+
+/// Refuse a request setting generation parameters the host cannot honour,
+/// naming them, rather than silently generating without them.
+pub(crate) fn reject_parameters(
+    host: &dyn Display,
+    request: &Request,
+    supported: &[&str],
+) -> Result<(), ProviderError> {
+    let unsupported: Vec<&str> = request
+        .parameters
+        .names()
+        .into_iter()
+        .filter(|name| !supported.contains(name))
+        .collect();
+    match unsupported.is_empty() {
+        true => Ok(()),
+        false => Err(ProviderError::Unsupported(format!(
+            "{host} does not support {}",
+            unsupported.join(", ")
+        ))),
+    }
+}
+
+/// Parse a `Retry-After` header given in seconds. HTTP-date values are
+/// ignored, leaving the client to fall back on its own backoff.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct Usage {
     prompt_tokens: Option<u64>,
     completion_tokens: Option<u64>,
@@ -29,4 +112,33 @@ mod tests {
         assert_eq!(usage.completion_tokens, None);
         assert_eq!(usage.total_tokens, None);
     }
+
+    #[test]
+    fn test_reject_parameters() {
+        let parameters = crate::Parameters {
+            temperature: Some(0.5),
+            seed: Some(7),
+            stop: vec!["END".to_string()],
+            ..Default::default()
+        };
+        let request = Request::new(&[]).with_parameters(&parameters);
+        assert!(reject_parameters(&"Host", &request, &["temperature", "seed", "stop"]).is_ok());
+        match reject_parameters(&"Host", &request, &["temperature"]) {
+            Err(ProviderError::Unsupported(detail)) => {
+                assert_eq!(detail, "Host does not support stop, seed")
+            }
+            other => panic!("expected Unsupported, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_retry_after_seconds() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(reqwest::header::RETRY_AFTER, "7".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap();
+        headers.insert(reqwest::header::RETRY_AFTER, date);
+        assert_eq!(retry_after(&headers), None);
+    }
 }
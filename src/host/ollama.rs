@@ -0,0 +1,276 @@
+use super::{reject_tools, CheckStatus, Usage};
+use crate::{
+    Completion, Delta, DeltaStream, Message, Provider, ProviderError, Request, ResponseFormat,
+};
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::io::{BufRead, BufReader};
+use url::Url;
+
+/// A provider for models served locally by Ollama via its `/api/chat` endpoint.
+pub struct Ollama {
+    name: String,
+    url: Url,
+}
+
+impl Ollama {
+    /// Address Ollama listens on unless configured otherwise.
+    pub const DEFAULT_URL: &'static str = "http://localhost:11434";
+
+    pub fn new<S: Into<String>>(name: S, url: Url) -> Self {
+        Self {
+            name: name.into(),
+            url,
+        }
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/api/{path}", self.url.as_str().trim_end_matches('/'))
+    }
+
+    /// Build the chat request body, passing generation parameters as model
+    /// `options` under Ollama's names.
+    fn payload(&self, request: &Request, stream: bool) -> serde_json::Value {
+        let parameters = request.parameters;
+        let mut options = serde_json::json!({
+            "temperature": parameters.temperature,
+            "top_p": parameters.top_p,
+            "num_predict": parameters.max_completion_tokens,
+            "seed": parameters.seed,
+            "presence_penalty": parameters.presence_penalty,
+            "frequency_penalty": parameters.frequency_penalty,
+        });
+        if !parameters.stop.is_empty() {
+            options["stop"] = serde_json::json!(parameters.stop);
+        }
+        let options = options.as_object_mut().unwrap();
+        options.retain(|_, value| !value.is_null());
+
+        let mut payload = serde_json::json!({
+            "model": self.name,
+            "messages": request.messages,
+            "stream": stream,
+        });
+        if !options.is_empty() {
+            payload["options"] = serde_json::json!(options);
+        }
+        if parameters.response_format == Some(ResponseFormat::Json) {
+            payload["format"] = serde_json::json!("json");
+        }
+        payload
+    }
+}
+
+impl Display for Ollama {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Ollama {}", self.name)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ChatResponse {
+    message: Option<Message>,
+    done: bool,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+impl ChatResponse {
+    fn usage(&self) -> Usage {
+        let total_tokens = match (self.prompt_eval_count, self.eval_count) {
+            (Some(prompt), Some(completion)) => Some(prompt + completion),
+            _ => None,
+        };
+        Usage {
+            prompt_tokens: self.prompt_eval_count,
+            completion_tokens: self.eval_count,
+            total_tokens,
+        }
+    }
+
+    /// Convert one line of the newline-delimited streaming output to a delta.
+    fn parse_line(line: &str) -> Result<Delta, ProviderError> {
+        let chunk = serde_json::from_str::<ChatResponse>(line)?;
+        let usage = chunk.done.then(|| chunk.usage());
+        Ok(Delta {
+            content: chunk.message.map(|m| m.content).unwrap_or_default(),
+            finish_reason: chunk.done_reason,
+            usage,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct TagsResponse {
+    models: Vec<TagsEntity>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct TagsEntity {
+    // Model name including its tag, e.g. `llama3:latest`
+    name: String,
+}
+
+impl Provider for Ollama {
+    fn send(
+        &self,
+        request: &Request,
+        client: &reqwest::blocking::Client,
+    ) -> Result<Completion, ProviderError> {
+        reject_tools(self, request)?;
+        let response = client
+            .post(self.endpoint("chat"))
+            .json(&self.payload(request, false))
+            .send()?
+            .check()?
+            .json::<ChatResponse>()?;
+
+        let usage = response.usage();
+        match response.message {
+            None => Err(ProviderError::EmptyResponse),
+            Some(message) => Ok(Completion {
+                message,
+                usage,
+                finish_reason: response.done_reason,
+            }),
+        }
+    }
+
+    fn send_stream(
+        &self,
+        request: &Request,
+        client: &reqwest::blocking::Client,
+    ) -> Result<DeltaStream, ProviderError> {
+        reject_tools(self, request)?;
+        let response = client
+            .post(self.endpoint("chat"))
+            .json(&self.payload(request, true))
+            .send()?
+            .check()?;
+
+        let deltas = BufReader::new(response)
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| ChatResponse::parse_line(&line?));
+        Ok(Box::new(deltas))
+    }
+
+    fn models(&self, client: &reqwest::blocking::Client) -> Result<Vec<String>, ProviderError> {
+        let response = client
+            .get(self.endpoint("tags"))
+            .send()?
+            .check()?
+            .json::<TagsResponse>()?;
+
+        Ok(response
+            .models
+            .into_iter()
+            .map(|model| model.name)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MockResponse, MockServer};
+    use serde_json::json;
+    use std::str::FromStr;
+
+    fn provider(server: &MockServer) -> Ollama {
+        Ollama::new("llama3", Url::from_str(server.url()).unwrap())
+    }
+
+    #[test]
+    fn test_ollama_send() {
+        let body = json!({
+            "model": "llama3",
+            "message": {"role": "assistant", "content": "Hello, user!"},
+            "done": true,
+            "done_reason": "stop",
+            "prompt_eval_count": 26,
+            "eval_count": 5
+        });
+        let server = MockServer::start(vec![MockResponse::json(200, body)]);
+
+        let client = reqwest::blocking::Client::new();
+        let context = [Message::user("Hello, assistant!")];
+        let Completion { message, usage, .. } = provider(&server)
+            .send(&Request::new(&context), &client)
+            .unwrap();
+        assert_eq!(message, Message::assistant("Hello, user!"));
+        assert_eq!(usage.prompt_tokens, Some(26));
+        assert_eq!(usage.completion_tokens, Some(5));
+        assert_eq!(usage.total_tokens, Some(31));
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/api/chat");
+        assert_eq!(request.json()["stream"], false);
+        assert_eq!(request.json()["model"], "llama3");
+    }
+
+    #[test]
+    fn test_ollama_payload_options() {
+        let provider = Ollama::new("llama3", Url::from_str(Ollama::DEFAULT_URL).unwrap());
+        let context = [Message::user("Hello, assistant!")];
+        let payload = provider.payload(&Request::new(&context), false);
+        assert!(payload.get("options").is_none());
+
+        let parameters = crate::Parameters {
+            temperature: Some(0.5),
+            max_completion_tokens: Some(64),
+            seed: Some(7),
+            response_format: Some(ResponseFormat::Json),
+            ..Default::default()
+        };
+        let request = Request::new(&context).with_parameters(&parameters);
+        let payload = provider.payload(&request, false);
+        assert_eq!(
+            payload["options"],
+            json!({"temperature": 0.5, "num_predict": 64, "seed": 7})
+        );
+        assert_eq!(payload["format"], "json");
+    }
+
+    #[test]
+    fn test_ollama_send_stream() {
+        let lines = [
+            json!({"message": {"role": "assistant", "content": "Hello"}, "done": false}),
+            json!({"message": {"role": "assistant", "content": ", user!"}, "done": false}),
+            json!({"message": {"role": "assistant", "content": ""}, "done": true,
+                   "done_reason": "stop", "prompt_eval_count": 2, "eval_count": 3}),
+        ];
+        let body = lines.map(|line| line.to_string()).join("\n");
+        let server = MockServer::start(vec![MockResponse::new(200, body)]);
+
+        let client = reqwest::blocking::Client::new();
+        let context = [Message::user("Hello, assistant!")];
+        let deltas = provider(&server)
+            .send_stream(&Request::new(&context), &client)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let text = deltas
+            .iter()
+            .map(|d| d.content.as_str())
+            .collect::<String>();
+        assert_eq!(text, "Hello, user!");
+        let last = deltas.last().unwrap();
+        assert_eq!(last.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(last.usage.as_ref().unwrap().total_tokens, Some(5));
+    }
+
+    #[test]
+    fn test_ollama_models() {
+        let body = json!({"models": [{"name": "llama3:latest"}, {"name": "mistral:7b"}]});
+        let server = MockServer::start(vec![MockResponse::json(200, body)]);
+
+        let client = reqwest::blocking::Client::new();
+        let models = provider(&server).models(&client).unwrap();
+        assert_eq!(models, vec!["llama3:latest", "mistral:7b"]);
+        assert_eq!(server.requests()[0].path, "/api/tags");
+    }
+}
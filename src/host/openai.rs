@@ -1,13 +1,35 @@
-use super::Usage;
-use crate::{Message, Provider, ProviderError, ProviderResponse};
+use super::sse::Events;
+use super::{CheckStatus, Usage};
+use crate::tokenizer::Encoding;
+use crate::{
+    Completion, Delta, DeltaStream, Parameters, Provider, ProviderError, ProviderResponse, Request,
+    ResponseFormat,
+};
+#[cfg(feature = "async")]
+use {super::check_async, crate::AsyncProvider};
 
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::io::BufReader;
 
-/// A provider that sends messages to the OpenAI API.
+/// A provider that sends messages to the OpenAI API, or any server speaking
+/// the same wire format (Azure OpenAI, vLLM, LiteLLM, etc.) via `with_base_url`.
+///
+/// # Examples
+///
+/// ```
+/// use air::host::OpenAI;
+///
+/// let model = OpenAI::new("gpt-4", "my-api-key")
+///     .with_base_url("http://localhost:8000/v1")
+///     .with_organization("org-123")
+///     .with_header("X-Request-Source", "air");
+/// ```
 pub struct OpenAI {
     name: String,
     key: String,
+    base_url: String,
+    headers: Vec<(String, String)>,
 }
 
 impl OpenAI {
@@ -17,8 +39,107 @@ impl OpenAI {
         Self {
             name: name.into(),
             key: key.into(),
+            base_url: Self::BASE_URL.to_string(),
+            headers: Vec::new(),
         }
     }
+
+    /// Use an alternative API root, such as `http://localhost:8000/v1`.
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Attribute requests to an organization via the `OpenAI-Organization` header.
+    pub fn with_organization<S: Into<String>>(self, organization: S) -> Self {
+        self.with_header("OpenAI-Organization", organization)
+    }
+
+    /// Attribute requests to a project via the `OpenAI-Project` header.
+    pub fn with_project<S: Into<String>>(self, project: S) -> Self {
+        self.with_header("OpenAI-Project", project)
+    }
+
+    /// Send an additional header with every request.
+    pub fn with_header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Start a request to `path` under the base URL with authentication and
+    /// any extra headers applied.
+    fn request(
+        &self,
+        client: &reqwest::blocking::Client,
+        method: reqwest::Method,
+        path: &str,
+    ) -> reqwest::blocking::RequestBuilder {
+        let mut request = client
+            .request(method, format!("{}/{path}", self.base_url))
+            .bearer_auth(&self.key);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+
+    /// Async counterpart of `request`.
+    #[cfg(feature = "async")]
+    fn request_async(
+        &self,
+        client: &reqwest::Client,
+        method: reqwest::Method,
+        path: &str,
+    ) -> reqwest::RequestBuilder {
+        let mut request = client
+            .request(method, format!("{}/{path}", self.base_url))
+            .bearer_auth(&self.key);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+
+    /// Build the chat completions request body, offering any tools.
+    fn payload(&self, request: &Request, stream: bool) -> serde_json::Value {
+        let mut payload = serde_json::json!({
+            "model": self.name,
+            "messages": request.messages,
+        });
+        apply_parameters(&mut payload, request.parameters);
+        if !request.tools.is_empty() {
+            payload["tools"] = serde_json::json!(request.tools);
+        }
+        if stream {
+            payload["stream"] = serde_json::Value::Bool(true);
+            payload["stream_options"] = serde_json::json!({ "include_usage": true });
+        }
+        payload
+    }
+
+    /// Model names from a `/models` listing. Compatible servers report their
+    /// own owners, so ownership is only filtered on the official API, where
+    /// newer models belong to `system` and fine-tuned ones to their owner.
+    fn chat_models(&self, response: ModelEndpointResponse) -> Vec<String> {
+        let official = self.base_url == Self::BASE_URL;
+        let owned = |data: &ModelEndpointEntity| {
+            matches!(
+                data.owned_by.as_str(),
+                "openai" | "system" | "openai-internal"
+            ) || data.id.starts_with("ft:")
+        };
+        response
+            .data
+            .into_iter()
+            .filter_map(|data| {
+                if data.object == "model" && (!official || owned(&data)) {
+                    Some(data.id)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
 }
 
 impl Display for OpenAI {
@@ -41,52 +162,312 @@ struct ModelEndpointEntity {
     // Entity description; should be `model` for models
     object: String,
 
-    // Entity owner; should be `openai` for OpenAI models
+    // Entity owner; `openai` or `system` for OpenAI models
     owned_by: String,
 }
 
+#[derive(Deserialize)]
+struct StreamChunk {
+    choices: Vec<StreamChoice>,
+
+    // Only populated on the final chunk when requested via `stream_options`
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct StreamChoice {
+    delta: StreamDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamDelta {
+    content: Option<String>,
+}
+
+impl StreamChunk {
+    /// Parse a single `data` payload from the completions event stream.
+    fn parse(data: &str) -> Result<Delta, ProviderError> {
+        let chunk = serde_json::from_str::<StreamChunk>(data)?;
+        let mut delta = Delta {
+            usage: chunk.usage,
+            ..Default::default()
+        };
+        if let Some(choice) = chunk.choices.into_iter().next() {
+            delta.content = choice.delta.content.unwrap_or_default();
+            delta.finish_reason = choice.finish_reason;
+        }
+        Ok(delta)
+    }
+}
+
+/// Add generation parameters to a chat completions request body. Shared with
+/// hosts speaking the same wire format.
+pub(super) fn apply_parameters(payload: &mut serde_json::Value, parameters: &Parameters) {
+    if let serde_json::Value::Object(fields) = serde_json::json!(parameters) {
+        for (name, value) in fields {
+            payload[name] = value;
+        }
+    }
+    if let Some(format) = parameters.response_format {
+        let kind = match format {
+            ResponseFormat::Text => "text",
+            ResponseFormat::Json => "json_object",
+        };
+        payload["response_format"] = serde_json::json!({ "type": kind });
+    }
+}
+
+/// Adapt a chat completions event stream into response deltas, ending at the
+/// `[DONE]` sentinel. Shared with hosts speaking the same wire format.
+pub(super) fn deltas(response: reqwest::blocking::Response) -> DeltaStream {
+    let deltas = Events::new(BufReader::new(response)).map_while(|event| match event {
+        Ok(data) if data == "[DONE]" => None,
+        Ok(data) => Some(StreamChunk::parse(&data)),
+        Err(err) => Some(Err(err)),
+    });
+    Box::new(deltas)
+}
+
 impl Provider for OpenAI {
+    fn encoding(&self) -> Option<Encoding> {
+        let name = self.name.as_str();
+        if ["gpt-4o", "gpt-4.1", "o1", "o3", "o4"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        {
+            Some(Encoding::O200k)
+        } else if name.starts_with("gpt-4") || name.starts_with("gpt-3.5") {
+            Some(Encoding::Cl100k)
+        } else {
+            None
+        }
+    }
+
     fn send(
         &self,
-        context: &[Message],
+        request: &Request,
         client: &reqwest::blocking::Client,
-    ) -> Result<(Message, Usage), ProviderError> {
-        let payload = serde_json::json!({
-            "model": self.name,
-            "messages": context,
-        });
-
-        let response = client
-            .post(format!("{}/chat/completions", Self::BASE_URL))
-            .json(&payload)
-            .bearer_auth(&self.key)
+    ) -> Result<Completion, ProviderError> {
+        let response = self
+            .request(client, reqwest::Method::POST, "chat/completions")
+            .json(&self.payload(request, false))
             .send()?
-            .error_for_status()?
+            .check()?
             .json::<ProviderResponse>()?;
 
         self.parse(response)
     }
 
+    fn send_stream(
+        &self,
+        request: &Request,
+        client: &reqwest::blocking::Client,
+    ) -> Result<DeltaStream, ProviderError> {
+        let response = self
+            .request(client, reqwest::Method::POST, "chat/completions")
+            .json(&self.payload(request, true))
+            .send()?
+            .check()?;
+
+        Ok(deltas(response))
+    }
+
     fn models(&self, client: &reqwest::blocking::Client) -> Result<Vec<String>, ProviderError> {
-        let response = client
-            .post(format!("{}/models", Self::BASE_URL))
-            .bearer_auth(&self.key)
+        let response = self
+            .request(client, reqwest::Method::GET, "models")
             .send()?
-            .error_for_status()?
+            .check()?
             .json::<ModelEndpointResponse>()?;
+        Ok(self.chat_models(response))
+    }
+}
 
-        let data = response
-            .data
-            .into_iter()
-            .filter_map(|data| {
-                if data.owned_by == "openai" && data.object == "model" {
-                    Some(data.id)
-                } else {
-                    None
-                }
-            })
-            .collect();
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl AsyncProvider for OpenAI {
+    fn encoding(&self) -> Option<Encoding> {
+        Provider::encoding(self)
+    }
+
+    async fn send(
+        &self,
+        request: &Request<'_>,
+        client: &reqwest::Client,
+    ) -> Result<Completion, ProviderError> {
+        let builder = self
+            .request_async(client, reqwest::Method::POST, "chat/completions")
+            .json(&self.payload(request, false));
+        let response = check_async(builder.send().await?)
+            .await?
+            .json::<ProviderResponse>()
+            .await?;
+
+        Provider::parse(self, response)
+    }
+
+    async fn models(&self, client: &reqwest::Client) -> Result<Vec<String>, ProviderError> {
+        let request = self.request_async(client, reqwest::Method::GET, "models");
+        let response = check_async(request.send().await?)
+            .await?
+            .json::<ModelEndpointResponse>()
+            .await?;
+
+        Ok(self.chat_models(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MockResponse, MockServer};
+    use crate::Message;
+    use serde_json::json;
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_openai_async_send() {
+        let body = json!({
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hello, user!"},
+                "finish_reason": "stop",
+                "logprobs": null
+            }],
+            "usage": {"prompt_tokens": 9, "completion_tokens": 4, "total_tokens": 13}
+        });
+        let server = MockServer::start(vec![MockResponse::json(200, body)]);
+        let provider = OpenAI::new("gpt-4", "secret").with_base_url(server.url());
+
+        let client = reqwest::Client::new();
+        let context = [Message::user("Hi")];
+        let Completion { message, usage, .. } =
+            AsyncProvider::send(&provider, &Request::new(&context), &client)
+                .await
+                .unwrap();
+        assert_eq!(message, Message::assistant("Hello, user!"));
+        assert_eq!(usage.total_tokens, Some(13));
+        assert_eq!(
+            server.requests()[0].header("authorization"),
+            Some("Bearer secret")
+        );
+    }
+
+    #[test]
+    fn test_openai_encoding() {
+        let encoding = |name| Provider::encoding(&OpenAI::new(name, "key"));
+        assert_eq!(encoding("gpt-4o-mini"), Some(Encoding::O200k));
+        assert_eq!(encoding("gpt-4-turbo"), Some(Encoding::Cl100k));
+        assert_eq!(encoding("gpt-3.5-turbo"), Some(Encoding::Cl100k));
+        assert_eq!(encoding("meta-llama/Meta-Llama-3-8B"), None);
+    }
+
+    #[test]
+    fn test_openai_base_url_and_headers() {
+        let body = json!({
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hello, user!"},
+                "finish_reason": "stop",
+                "logprobs": null
+            }],
+            "usage": {"prompt_tokens": 9, "completion_tokens": 4, "total_tokens": 13}
+        });
+        let server = MockServer::start(vec![MockResponse::json(200, body)]);
+        let provider = OpenAI::new("gpt-4", "secret")
+            .with_base_url(format!("{}/v1/", server.url()))
+            .with_organization("org-123")
+            .with_project("proj-456")
+            .with_header("X-Extra", "yes");
+
+        let client = reqwest::blocking::Client::new();
+        let Completion { message, usage, .. } =
+            Provider::send(&provider, &Request::new(&[Message::user("Hi")]), &client).unwrap();
+        assert_eq!(message, Message::assistant("Hello, user!"));
+        assert_eq!(usage.total_tokens, Some(13));
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/v1/chat/completions");
+        assert_eq!(request.header("authorization"), Some("Bearer secret"));
+        assert_eq!(request.header("openai-organization"), Some("org-123"));
+        assert_eq!(request.header("openai-project"), Some("proj-456"));
+        assert_eq!(request.header("x-extra"), Some("yes"));
+    }
+
+    #[test]
+    fn test_openai_payload_parameters() {
+        let parameters = Parameters {
+            temperature: Some(0.5),
+            max_completion_tokens: Some(256),
+            stop: vec!["END".to_string()],
+            response_format: Some(ResponseFormat::Json),
+            ..Default::default()
+        };
+        let context = [Message::user("Hi")];
+        let request = Request::new(&context).with_parameters(&parameters);
+        let payload = OpenAI::new("gpt-4", "key").payload(&request, false);
+        assert_eq!(payload["temperature"], json!(0.5));
+        assert_eq!(payload["max_completion_tokens"], json!(256));
+        assert_eq!(payload["stop"], json!(["END"]));
+        assert_eq!(payload["response_format"], json!({"type": "json_object"}));
+        assert!(payload.get("seed").is_none());
+    }
+
+    #[test]
+    fn test_openai_models_official_owners() {
+        let response = serde_json::from_value(json!({
+            "object": "list",
+            "data": [
+                {"id": "gpt-3.5-turbo", "object": "model", "owned_by": "openai"},
+                {"id": "gpt-4o", "object": "model", "owned_by": "system"},
+                {"id": "ft:gpt-4o-mini:acme::abc123", "object": "model", "owned_by": "acme"},
+                {"id": "someone-elses", "object": "model", "owned_by": "acme"}
+            ]
+        }))
+        .unwrap();
+        let models = OpenAI::new("gpt-4", "secret").chat_models(response);
+        assert_eq!(
+            models,
+            vec!["gpt-3.5-turbo", "gpt-4o", "ft:gpt-4o-mini:acme::abc123"]
+        );
+    }
+
+    #[test]
+    fn test_openai_models_custom_base_url() {
+        let body = json!({
+            "object": "list",
+            "data": [
+                {"id": "meta-llama/Meta-Llama-3-8B", "object": "model", "owned_by": "vllm"},
+                {"id": "embedding", "object": "embedding", "owned_by": "vllm"}
+            ]
+        });
+        let server = MockServer::start(vec![MockResponse::json(200, body)]);
+        let provider = OpenAI::new("gpt-4", "secret").with_base_url(server.url());
+
+        let client = reqwest::blocking::Client::new();
+        let models = Provider::models(&provider, &client).unwrap();
+        assert_eq!(models, vec!["meta-llama/Meta-Llama-3-8B"]);
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/models");
+    }
+
+    #[test]
+    fn test_stream_chunk_content() {
+        let data =
+            r#"{"id":"1","choices":[{"index":0,"delta":{"content":"Hel"},"finish_reason":null}]}"#;
+        let delta = StreamChunk::parse(data).unwrap();
+        assert_eq!(delta.content, "Hel");
+        assert_eq!(delta.finish_reason, None);
+        assert_eq!(delta.usage, None);
+    }
 
-        Ok(data)
+    #[test]
+    fn test_stream_chunk_usage_only() {
+        let data = r#"{"id":"1","choices":[],"usage":{"prompt_tokens":3,"completion_tokens":2,"total_tokens":5}}"#;
+        let delta = StreamChunk::parse(data).unwrap();
+        assert!(delta.content.is_empty());
+        assert_eq!(delta.usage.unwrap().total_tokens, Some(5));
     }
 }
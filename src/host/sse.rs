@@ -0,0 +1,86 @@
+use std::io::BufRead;
+
+use crate::ProviderError;
+
+/// Iterator over the `data` payloads of a server-sent event stream. Multi-line
+/// payloads are joined with newlines; comments and other fields are ignored.
+pub(crate) struct Events<R: BufRead> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> Events<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Events<R> {
+    type Item = Result<String, ProviderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut data: Option<String> = None;
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Err(err) => return Some(Err(err.into())),
+                // EOF; flush any event missing its terminating blank line
+                Ok(0) => return data.map(Ok),
+                Ok(_) => {}
+            }
+
+            let line = self.line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                // Blank line dispatches the event, if any data was collected
+                match data {
+                    Some(data) => return Some(Ok(data)),
+                    None => continue,
+                }
+            }
+
+            if let Some(value) = line.strip_prefix("data:") {
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                match data.as_mut() {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => data = Some(value.to_owned()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn events(stream: &str) -> Vec<String> {
+        Events::new(Cursor::new(stream.to_owned()))
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_events_data_only() {
+        let stream = "data: first\n\ndata: second\r\n\r\n";
+        assert_eq!(events(stream), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_events_skip_comments_and_fields() {
+        let stream = ": keep-alive\n\nevent: message\nid: 1\ndata:{\"a\":1}\n\n";
+        assert_eq!(events(stream), vec![r#"{"a":1}"#]);
+    }
+
+    #[test]
+    fn test_events_multiline_and_unterminated() {
+        let stream = "data: one\ndata: two\n\ndata: [DONE]";
+        assert_eq!(events(stream), vec!["one\ntwo", "[DONE]"]);
+    }
+}
@@ -1,13 +1,22 @@
-use std::{collections::HashMap, fmt::Display, str::FromStr};
+use std::{fmt::Display, str::FromStr, time::Duration};
 
 use enum_iterator::Sequence;
 use host::Usage;
 use reqwest::blocking;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use tokenizer::Encoding;
+use tool::{Tool, ToolCall};
 
 pub mod client;
+pub mod export;
 pub mod host;
+pub mod tokenizer;
+pub mod tool;
 pub mod transcript;
+pub mod tree;
+
+#[cfg(test)]
+mod test_utils;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Sequence)]
 #[serde(rename_all = "lowercase")]
@@ -21,6 +30,9 @@ pub enum Role {
 
     /// A response from the model
     Assistant,
+
+    /// The result of a tool call requested by the model
+    Tool,
 }
 
 impl FromStr for Role {
@@ -31,6 +43,7 @@ impl FromStr for Role {
             "system" => Ok(Role::System),
             "user" => Ok(Role::User),
             "assistant" => Ok(Role::Assistant),
+            "tool" => Ok(Role::Tool),
             _ => Err(s.to_owned()),
         }
     }
@@ -42,6 +55,7 @@ impl Display for Role {
             Role::System => write!(f, "System"),
             Role::User => write!(f, "User"),
             Role::Assistant => write!(f, "Assistant"),
+            Role::Tool => write!(f, "Tool"),
         }
     }
 }
@@ -49,7 +63,23 @@ impl Display for Role {
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct Message {
     pub role: Role,
+
+    // assistant messages carrying only tool calls have a null content
+    #[serde(default, deserialize_with = "nullable")]
     pub content: String,
+
+    /// Tools the model asked to call, on assistant messages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+
+    /// The call answered by a `Role::Tool` message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// Deserialize a string that may be given as `null`.
+fn nullable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 /// Generate `Message` constructors named for each provided role.
@@ -57,10 +87,7 @@ macro_rules! expand_roles {
     ($($var:ident),*) => {
         $(
             pub fn $var<S: Into<String>>(content: S) -> Self {
-                Self {
-                    role: Role::from_str(stringify!($var)).unwrap(),
-                    content: content.into(),
-                }
+                Self::new(Role::from_str(stringify!($var)).unwrap(), content)
             }
         )*
     };
@@ -71,16 +98,26 @@ impl Message {
         Self {
             role,
             content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
         }
     }
 
     expand_roles!(system, user, assistant);
+
+    /// The result of the tool call identified by `call_id`.
+    pub fn tool<S: Into<String>>(call_id: S, content: S) -> Self {
+        Self {
+            tool_call_id: Some(call_id.into()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct ModelOutput {
     index: usize,
-    message: HashMap<String, String>,
+    message: Message,
     finish_reason: String,
     logprobs: Option<serde_json::Value>,
 }
@@ -91,10 +128,349 @@ pub struct ProviderResponse {
     usage: Usage,
 }
 
+impl ProviderResponse {
+    /// Extract the first choice's message and the usage statistics.
+    fn into_parts(self) -> Result<Completion, ProviderError> {
+        let Some(choice) = self.choices.into_iter().next() else {
+            return Err(ProviderError::EmptyResponse);
+        };
+        let message = choice.message;
+        let withheld = message.content.is_empty() && message.tool_calls.is_empty();
+        if withheld && choice.finish_reason == "content_filter" {
+            return Err(ProviderError::ContentFiltered(ApiError {
+                message: "The response was withheld by the content filter".to_string(),
+                code: Some(choice.finish_reason),
+                kind: None,
+            }));
+        }
+        Ok(Completion {
+            message,
+            usage: self.usage,
+            finish_reason: Some(choice.finish_reason),
+        })
+    }
+}
+
+/// A model's complete response to a `Request`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    pub message: Message,
+    pub usage: Usage,
+
+    /// Why the model stopped generating, e.g. `stop` or `length`, if reported
+    pub finish_reason: Option<String>,
+}
+
+impl Completion {
+    pub fn new(message: Message, usage: Usage) -> Self {
+        Self {
+            message,
+            usage,
+            finish_reason: None,
+        }
+    }
+}
+
+/// An incremental piece of a model response delivered while it is generated.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Delta {
+    /// Text generated since the previous delta
+    pub content: String,
+
+    /// Reason the model stopped generating, present on the final choice delta
+    pub finish_reason: Option<String>,
+
+    /// Usage statistics, if the provider reports them on this delta
+    pub usage: Option<Usage>,
+}
+
+/// Format a model is asked to reply in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    #[default]
+    Text,
+
+    /// A single valid JSON object (JSON mode)
+    Json,
+}
+
+impl FromStr for ResponseFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(ResponseFormat::Text),
+            "json" => Ok(ResponseFormat::Json),
+            _ => Err(format!(
+                "unknown response format `{s}`; expected text or json"
+            )),
+        }
+    }
+}
+
+impl Display for ResponseFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResponseFormat::Text => write!(f, "text"),
+            ResponseFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Settings controlling how a model generates its reply. Unset parameters
+/// are left to the provider's defaults; providers refuse requests setting
+/// parameters they cannot honour.
+///
+/// # Examples
+///
+/// ```
+/// use air::{Parameters, ResponseFormat};
+///
+/// let parameters = Parameters {
+///     temperature: Some(0.2),
+///     response_format: Some(ResponseFormat::Json),
+///     ..Default::default()
+/// };
+/// assert_eq!(parameters.names(), vec!["temperature", "response_format"]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Parameters {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+
+    /// Upper bound on tokens generated in the reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+
+    /// Sequences at which the model stops generating
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// Parameters of a `Request` that sets none.
+static NO_PARAMETERS: Parameters = Parameters {
+    temperature: None,
+    top_p: None,
+    max_completion_tokens: None,
+    stop: Vec::new(),
+    seed: None,
+    presence_penalty: None,
+    frequency_penalty: None,
+    response_format: None,
+};
+
+impl Parameters {
+    /// Fill the parameters left unset from `defaults`.
+    pub fn or(self, defaults: &Parameters) -> Self {
+        Self {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            max_completion_tokens: self
+                .max_completion_tokens
+                .or(defaults.max_completion_tokens),
+            stop: match self.stop.is_empty() {
+                true => defaults.stop.clone(),
+                false => self.stop,
+            },
+            seed: self.seed.or(defaults.seed),
+            presence_penalty: self.presence_penalty.or(defaults.presence_penalty),
+            frequency_penalty: self.frequency_penalty.or(defaults.frequency_penalty),
+            response_format: self.response_format.or(defaults.response_format),
+        }
+    }
+
+    /// Names of the parameters that are set, as used by OpenAI. A plain text
+    /// response format is every model's default, so it is not listed.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            ("temperature", self.temperature.is_some()),
+            ("top_p", self.top_p.is_some()),
+            (
+                "max_completion_tokens",
+                self.max_completion_tokens.is_some(),
+            ),
+            ("stop", !self.stop.is_empty()),
+            ("seed", self.seed.is_some()),
+            ("presence_penalty", self.presence_penalty.is_some()),
+            ("frequency_penalty", self.frequency_penalty.is_some()),
+            (
+                "response_format",
+                self.response_format
+                    .is_some_and(|format| format != ResponseFormat::Text),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect()
+    }
+}
+
+/// Everything sent to a provider for a single completion: the conversation so
+/// far, any tools the model may call and the generation parameters.
+#[derive(Clone, Copy, Debug)]
+pub struct Request<'a> {
+    pub messages: &'a [Message],
+    pub tools: &'a [Tool],
+    pub parameters: &'a Parameters,
+}
+
+impl<'a> Request<'a> {
+    pub fn new(messages: &'a [Message]) -> Self {
+        Self {
+            messages,
+            tools: &[],
+            parameters: &NO_PARAMETERS,
+        }
+    }
+
+    pub fn with_tools(mut self, tools: &'a [Tool]) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    pub fn with_parameters(mut self, parameters: &'a Parameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+}
+
+impl Default for Request<'_> {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+/// Iterator over the deltas of a streamed model response.
+pub type DeltaStream = Box<dyn Iterator<Item = Result<Delta, ProviderError>>>;
+
+/// Error details reported by a provider in the body of a failed response,
+/// e.g. OpenAI's `{"error": {"message": ..., "code": ...}}`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ApiError {
+    /// Human-readable explanation from the provider
+    pub message: String,
+
+    /// Machine-readable error code, e.g. `context_length_exceeded`
+    pub code: Option<String>,
+
+    /// Error category, e.g. `invalid_request_error`
+    pub kind: Option<String>,
+}
+
+impl ApiError {
+    /// Extract error details from a response body, accepting the common
+    /// `{"error": {...}}` and `{"error": "..."}` shapes and falling back to
+    /// the raw text. Returns `None` for an empty body.
+    pub fn parse(body: &str) -> Option<Self> {
+        let text = |value: &serde_json::Value| match value {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        };
+
+        let body = body.trim();
+        if body.is_empty() {
+            return None;
+        }
+        let value = serde_json::from_str::<serde_json::Value>(body).unwrap_or_default();
+        let error = match value.get("error") {
+            Some(serde_json::Value::String(message)) => ApiError {
+                message: message.clone(),
+                ..Default::default()
+            },
+            Some(error) if error.is_object() => ApiError {
+                message: error.get("message").and_then(text).unwrap_or_default(),
+                code: error.get("code").and_then(text),
+                kind: error.get("type").and_then(text),
+            },
+            _ => ApiError {
+                message: body.to_string(),
+                ..Default::default()
+            },
+        };
+        Some(error)
+    }
+
+    /// Whether the code, kind or message mention any of `needles`.
+    fn mentions(&self, needles: &[&str]) -> bool {
+        let haystacks = [
+            self.code.as_deref().unwrap_or_default(),
+            self.kind.as_deref().unwrap_or_default(),
+            &self.message.to_lowercase(),
+        ];
+        needles
+            .iter()
+            .any(|needle| haystacks.iter().any(|h| h.contains(needle)))
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{} ({code})", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Format optional provider details as a `: details` suffix.
+fn detail(error: &Option<ApiError>) -> String {
+    match error {
+        Some(error) => format!(": {error}"),
+        None => String::new(),
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ProviderError {
-    #[error("Failed to submit request to the server: HTTP error {0}")]
-    HttpError(reqwest::StatusCode),
+    #[error(
+        "Failed to submit request to the server: HTTP error {status}{}",
+        detail(error)
+    )]
+    HttpError {
+        status: reqwest::StatusCode,
+        error: Option<ApiError>,
+    },
+
+    #[error("Authentication failed{}", detail(error))]
+    AuthenticationFailed { error: Option<ApiError> },
+
+    #[error("Rate limited by the server{}{}", match retry_after {
+        Some(delay) => format!("; retry after {}s", delay.as_secs()),
+        None => String::new(),
+    }, detail(error))]
+    RateLimited {
+        retry_after: Option<Duration>,
+        error: Option<ApiError>,
+    },
+
+    #[error("The conversation is too long for the model: {0}")]
+    ContextLengthExceeded(ApiError),
+
+    #[error("The provider refused the request under its content policy: {0}")]
+    ContentFiltered(ApiError),
+
+    #[error("The request timed out before the server responded")]
+    Timeout,
+
+    #[error("Could not connect to the server: {0}. Check the host URL and your network")]
+    ConnectionFailed(String),
 
     #[error("Failed to parse server response: {0}")]
     ParsingError(#[from] serde_json::Error),
@@ -102,15 +478,98 @@ pub enum ProviderError {
     #[error("No response from the server despite successful request")]
     EmptyResponse,
 
-    #[error("An unknown error occurred")]
-    UnknownError,
+    #[error("Failed to read streamed response: {0}")]
+    StreamError(#[from] std::io::Error),
+
+    #[error("Context of ~{estimated} tokens exceeds the budget of {limit} tokens")]
+    BudgetExceeded { estimated: usize, limit: usize },
+
+    #[error("Not supported by the provider: {0}")]
+    Unsupported(String),
+
+    #[error("The model was still calling tools after {0} rounds")]
+    ToolRoundsExceeded(usize),
+
+    #[error("There is no prompt to respond to")]
+    NoPrompt,
+
+    #[error("Cannot send the header {0}: its name or value has characters headers do not allow")]
+    InvalidHeader(String),
+
+    #[error("An unknown error occurred: {0}")]
+    UnknownError(String),
+}
+
+impl ProviderError {
+    /// Classify a failed response by its status and body.
+    pub fn from_response(
+        status: reqwest::StatusCode,
+        retry_after: Option<Duration>,
+        body: &str,
+    ) -> Self {
+        use reqwest::StatusCode;
+
+        let error = ApiError::parse(body);
+        let mentions = |needles: &[&str]| error.as_ref().is_some_and(|e| e.mentions(needles));
+        if mentions(&[
+            "context_length_exceeded",
+            "maximum context length",
+            "prompt is too long",
+        ]) || status == StatusCode::PAYLOAD_TOO_LARGE
+        {
+            return ProviderError::ContextLengthExceeded(error.unwrap_or_default());
+        }
+        if mentions(&["content_filter", "content_policy_violation"]) {
+            return ProviderError::ContentFiltered(error.unwrap_or_default());
+        }
+
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                ProviderError::AuthenticationFailed { error }
+            }
+            // an exhausted quota is reported as 429 but will not clear by waiting
+            StatusCode::TOO_MANY_REQUESTS if !mentions(&["insufficient_quota"]) => {
+                ProviderError::RateLimited { retry_after, error }
+            }
+            _ => ProviderError::HttpError { status, error },
+        }
+    }
+
+    /// Process exit status describing the error for command line use,
+    /// following the BSD `sysexits.h` conventions.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ProviderError::NoPrompt
+            | ProviderError::Unsupported(_)
+            | ProviderError::InvalidHeader(_) => 64,
+            ProviderError::ContextLengthExceeded(_)
+            | ProviderError::ContentFiltered(_)
+            | ProviderError::BudgetExceeded { .. } => 65,
+            ProviderError::HttpError { .. } | ProviderError::ConnectionFailed(_) => 69,
+            ProviderError::RateLimited { .. } | ProviderError::Timeout => 75,
+            ProviderError::ParsingError(_)
+            | ProviderError::EmptyResponse
+            | ProviderError::StreamError(_)
+            | ProviderError::ToolRoundsExceeded(_) => 76,
+            ProviderError::AuthenticationFailed { .. } => 77,
+            ProviderError::UnknownError(_) => 1,
+        }
+    }
 }
 
 impl From<reqwest::Error> for ProviderError {
     fn from(value: reqwest::Error) -> Self {
-        match value.status() {
-            Some(status) => ProviderError::HttpError(status),
-            None => ProviderError::UnknownError,
+        if value.is_timeout() {
+            ProviderError::Timeout
+        } else if value.is_connect() {
+            ProviderError::ConnectionFailed(value.to_string())
+        } else if let Some(status) = value.status() {
+            ProviderError::HttpError {
+                status,
+                error: None,
+            }
+        } else {
+            ProviderError::UnknownError(value.to_string())
         }
     }
 }
@@ -118,17 +577,14 @@ impl From<reqwest::Error> for ProviderError {
 pub trait Provider: Display {
     /// Helper method for `send` implementers to extract the relevant details
     /// from a provider's deserialized response object.
-    fn parse(&self, mut response: ProviderResponse) -> Result<(Message, Usage), ProviderError> {
-        match response.choices.first_mut() {
-            None => Err(ProviderError::EmptyResponse),
-            Some(choice) => {
-                // avoiding a needless copy of what may be a large response
-                match choice.message.remove("content") {
-                    None => Err(ProviderError::EmptyResponse),
-                    Some(text) => Ok((Message::assistant(text), response.usage)),
-                }
-            }
-        }
+    fn parse(&self, response: ProviderResponse) -> Result<Completion, ProviderError> {
+        response.into_parts()
+    }
+
+    /// The byte-pair encoding used by the provider's model, if known, so that
+    /// clients can count tokens locally. `None` selects a heuristic count.
+    fn encoding(&self) -> Option<Encoding> {
+        None
     }
 
     /// A list of valid model names for the provider.
@@ -136,11 +592,51 @@ pub trait Provider: Display {
 
     /// Send a message and accompanying context to the model using the provided
     /// HTTP client, returning the response message and usage statistics.
+    /// Providers without tool calling reject requests offering tools.
     fn send(
         &self,
-        context: &[Message],
+        request: &Request,
+        client: &blocking::Client,
+    ) -> Result<Completion, ProviderError>;
+
+    /// Send a message and accompanying context to the model, returning an
+    /// iterator over the response deltas as they are generated. Providers
+    /// without native streaming yield the complete response as a single delta.
+    fn send_stream(
+        &self,
+        request: &Request,
         client: &blocking::Client,
-    ) -> Result<(Message, Usage), ProviderError>;
+    ) -> Result<DeltaStream, ProviderError> {
+        let completion = self.send(request, client)?;
+        let delta = Delta {
+            content: completion.message.content,
+            finish_reason: completion.finish_reason,
+            usage: Some(completion.usage),
+        };
+        Ok(Box::new(std::iter::once(Ok(delta))))
+    }
+}
+
+/// The asynchronous counterpart of `Provider`, for use with `reqwest`'s async
+/// client inside an existing runtime. Available with the `async` feature.
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+pub trait AsyncProvider: Display + Send + Sync {
+    /// The byte-pair encoding used by the provider's model, if known.
+    fn encoding(&self) -> Option<Encoding> {
+        None
+    }
+
+    /// A list of valid model names for the provider.
+    async fn models(&self, client: &reqwest::Client) -> Result<Vec<String>, ProviderError>;
+
+    /// Send a message and accompanying context to the model using the provided
+    /// HTTP client, returning the response message and usage statistics.
+    async fn send(
+        &self,
+        request: &Request<'_>,
+        client: &reqwest::Client,
+    ) -> Result<Completion, ProviderError>;
 }
 
 #[cfg(test)]
@@ -154,6 +650,134 @@ mod tests {
         assert_eq!(serialized, r#""user""#);
     }
 
+    #[test]
+    fn test_api_error_parse_shapes() {
+        let openai = serde_json::json!({
+            "error": {"message": "Bad key", "type": "invalid_request_error", "code": "invalid_api_key"}
+        });
+        let error = ApiError::parse(&openai.to_string()).unwrap();
+        assert_eq!(error.message, "Bad key");
+        assert_eq!(error.code.as_deref(), Some("invalid_api_key"));
+        assert_eq!(error.kind.as_deref(), Some("invalid_request_error"));
+
+        let ollama = r#"{"error": "model 'llama9' not found"}"#;
+        assert_eq!(
+            ApiError::parse(ollama).unwrap().message,
+            "model 'llama9' not found"
+        );
+
+        assert_eq!(
+            ApiError::parse("Bad Gateway").unwrap().message,
+            "Bad Gateway"
+        );
+        assert_eq!(ApiError::parse("  "), None);
+    }
+
+    #[test]
+    fn test_parameters_or_defaults() {
+        let defaults = Parameters {
+            temperature: Some(1.0),
+            stop: vec!["END".to_string()],
+            response_format: Some(ResponseFormat::Json),
+            ..Default::default()
+        };
+        let parameters = Parameters {
+            temperature: Some(0.0),
+            seed: Some(7),
+            ..Default::default()
+        }
+        .or(&defaults);
+        assert_eq!(parameters.temperature, Some(0.0));
+        assert_eq!(parameters.seed, Some(7));
+        assert_eq!(parameters.stop, vec!["END".to_string()]);
+        assert_eq!(
+            parameters.names(),
+            vec!["temperature", "stop", "seed", "response_format"]
+        );
+
+        let text = Parameters {
+            response_format: Some(ResponseFormat::Text),
+            ..Default::default()
+        };
+        assert!(text.names().is_empty());
+    }
+
+    #[test]
+    fn test_provider_error_exit_codes() {
+        let auth = ProviderError::AuthenticationFailed { error: None };
+        assert_eq!(auth.exit_code(), 77);
+        assert_eq!(ProviderError::Timeout.exit_code(), 75);
+        assert_eq!(ProviderError::NoPrompt.exit_code(), 64);
+        assert_eq!(ProviderError::UnknownError(String::new()).exit_code(), 1);
+    }
+
+    #[test]
+    fn test_provider_error_classification() {
+        use reqwest::StatusCode;
+
+        let context = serde_json::json!({
+            "error": {
+                "message": "This model's maximum context length is 8192 tokens",
+                "code": "context_length_exceeded"
+            }
+        });
+        let err = ProviderError::from_response(StatusCode::BAD_REQUEST, None, &context.to_string());
+        assert!(matches!(err, ProviderError::ContextLengthExceeded(_)));
+
+        let anthropic = serde_json::json!({
+            "type": "error",
+            "error": {
+                "type": "invalid_request_error",
+                "message": "prompt is too long: 210000 tokens > 200000 maximum"
+            }
+        });
+        let err =
+            ProviderError::from_response(StatusCode::BAD_REQUEST, None, &anthropic.to_string());
+        assert!(matches!(err, ProviderError::ContextLengthExceeded(_)));
+
+        let filtered = r#"{"error": {"message": "Rejected", "code": "content_policy_violation"}}"#;
+        let err = ProviderError::from_response(StatusCode::BAD_REQUEST, None, filtered);
+        assert!(matches!(err, ProviderError::ContentFiltered(_)));
+
+        let quota =
+            r#"{"error": {"message": "You exceeded your quota", "code": "insufficient_quota"}}"#;
+        let err = ProviderError::from_response(StatusCode::TOO_MANY_REQUESTS, None, quota);
+        assert!(matches!(err, ProviderError::HttpError { status, .. } if status == 429));
+
+        let err = ProviderError::from_response(StatusCode::FORBIDDEN, None, "");
+        assert!(matches!(
+            err,
+            ProviderError::AuthenticationFailed { error: None }
+        ));
+        assert_eq!(err.to_string(), "Authentication failed");
+    }
+
+    #[test]
+    fn test_provider_response_tool_calls() {
+        let response = serde_json::json!({
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{}"}
+                    }]
+                },
+                "finish_reason": "tool_calls",
+                "logprobs": null
+            }],
+            "usage": {"total_tokens": 12}
+        });
+        let response: ProviderResponse = serde_json::from_value(response).unwrap();
+        let completion = response.into_parts().unwrap();
+        assert_eq!(completion.message.content, "");
+        assert_eq!(completion.message.tool_calls[0].name, "get_weather");
+        assert_eq!(completion.finish_reason.as_deref(), Some("tool_calls"));
+    }
+
     #[test]
     fn test_message_serialize() {
         let message = Message::new(Role::User, "Hello, world!".to_string());
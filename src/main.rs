@@ -1,57 +1,154 @@
-use air::client::{Client, ClientConfig};
-use air::host::{Custom, OpenAI};
-use air::transcript::{load, Transcript};
-use air::Message;
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use air::client::{BudgetPolicy, Client, ClientConfig, RetryPolicy};
+use air::export::{render, ExportFormat, UsageSummary};
+use air::host::{Anthropic, Custom, CustomSpec, LlamaCpp, Ollama, OpenAI};
+use air::transcript::{load_entries, load_tree, save_tree, Metadata, Transcript, TranscriptFormat};
+use air::tree::ConversationTree;
+use air::{Message, Parameters, ProviderError, ResponseFormat, Role};
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use dotenvy::dotenv;
-use inquire::{Password, Text};
-use rustyline::{error::ReadlineError, DefaultEditor};
-use serde::Serialize;
+use inquire::{validator::Validation, Password, Select, Text};
+use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
+use serde::{Deserialize, Serialize};
 use std::convert::From;
+use std::fmt::Display;
 use std::fs::File;
-use std::io::{stdout, LineWriter, Write};
-use std::path::PathBuf;
+use std::io::{stdout, IsTerminal, LineWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::str::FromStr;
-use std::thread::sleep;
 use std::time::Duration;
 use url::Url;
 
+mod commands;
+mod config;
+mod models;
 mod profile;
-use profile::Profile;
+use commands::{join_lines, open_editor, ReplHelper, SlashCommand};
+use models::ModelCache;
+use profile::{Profile, Profiles, SecretStore};
 
-#[derive(clap::ValueEnum, Copy, Clone, Serialize, Default, Debug)]
+#[derive(clap::ValueEnum, Copy, Clone, Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum Host {
     #[default]
     Custom,
+    // named as on the command line
+    #[serde(rename = "open-ai")]
     OpenAI,
+    Anthropic,
+    Ollama,
+    LlamaCpp,
+}
+
+impl Host {
+    /// Whether the host needs an API key; locally served models do not.
+    fn requires_key(&self) -> bool {
+        matches!(self, Host::OpenAI | Host::Anthropic)
+    }
+
+    /// Model used when neither `--name` nor the profile names one.
+    fn default_model(&self) -> Option<&'static str> {
+        match self {
+            Host::OpenAI => Some("gpt-3.5-turbo"),
+            Host::Anthropic => Some("claude-3-haiku-20240307"),
+            Host::Ollama => Some("llama3"),
+            Host::LlamaCpp | Host::Custom => None,
+        }
+    }
+}
+
+impl Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self.to_possible_value().expect("no hosts are skipped");
+        write!(f, "{}", value.get_name())
+    }
 }
 
 #[derive(Parser, Default, Clone)]
 #[command(version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct Args {
-    /// Host for the model
-    #[clap(long, value_enum, default_value_t = Host::OpenAI)]
-    host: Host,
+    /// Host for the model [default: the profile's, else open-ai]
+    #[clap(long, value_enum, default_value = None)]
+    host: Option<Host>,
 
-    /// Name of host model, if applicable
+    /// Name of host model, if applicable, overriding the profile
     #[clap(short, long, default_value = None)]
     name: Option<String>,
 
+    /// Address of the host server, for locally served and custom models
+    #[clap(long, default_value = None, value_parser = parse_url)]
+    url: Option<Url>,
+
+    /// Header to send to a custom host, as `Name: value`, replacing any of the same name; may be repeated
+    #[clap(long = "header", default_value = None, value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Seconds to wait for a custom host to reply
+    #[clap(long, default_value = None)]
+    timeout: Option<u64>,
+
+    /// TOML or JSON file describing a custom host's requests and responses
+    #[clap(long, default_value = None)]
+    spec: Option<PathBuf>,
+
+    /// API root for OpenAI-compatible and Anthropic hosts, overriding the profile
+    #[clap(long, default_value = None, value_parser = parse_url)]
+    base_url: Option<Url>,
+
     /// Maximum context size in tokens to allow; useful for billing purposes
     #[clap(short, long, default_value = None)]
     max_tokens: Option<usize>,
 
+    /// What to do when the context exceeds `max-tokens`: refuse, truncate or summarize
+    #[clap(long, default_value_t = BudgetPolicy::Refuse)]
+    budget_policy: BudgetPolicy,
+
+    /// Directory of tiktoken rank files for exact token counts [env: AIR_TOKENIZER_DIR]
+    #[clap(long, default_value = None)]
+    tokenizer_dir: Option<PathBuf>,
+
+    /// Times to retry a request after rate limiting or a server error
+    #[clap(long, default_value_t = RetryPolicy::default().max_retries)]
+    max_retries: u32,
+
+    /// Delay in milliseconds before the first retry, doubling thereafter
+    #[clap(long, default_value_t = RetryPolicy::default().base_delay.as_millis() as u64)]
+    retry_delay: u64,
+
+    /// Longest delay in milliseconds before a retry, including one asked for by the server
+    #[clap(long, default_value_t = RetryPolicy::default().max_delay.as_millis() as u64)]
+    max_retry_delay: u64,
+
+    /// Randomize each retry delay between half and all of its value (the default)
+    #[clap(long, overrides_with = "no_retry_jitter")]
+    retry_jitter: bool,
+
+    /// Wait exactly the computed delay before each retry
+    #[clap(long, overrides_with = "retry_jitter")]
+    no_retry_jitter: bool,
+
+    /// System message to start the conversation with, overriding the profile
+    #[clap(long, default_value = None, conflicts_with = "system_file")]
+    system: Option<String>,
+
+    /// File holding the system message to start the conversation with
+    #[clap(long, default_value = None)]
+    system_file: Option<PathBuf>,
+
     #[clap(short, long, default_value = None)]
     /// Output location to save transcript
     output: Option<PathBuf>,
 
     #[clap(short, long, default_value = None)]
-    /// Location to load transcript for context initialization
+    /// Location to load transcript for context initialization; its format is detected
     input: Option<PathBuf>,
 
+    /// Format of the saved transcript: text, json or jsonl [default: from the output extension]
+    #[clap(long, default_value = None)]
+    format: Option<TranscriptFormat>,
+
     #[clap(short, long, default_value_t = false)]
     /// Verbose output
     verbose: bool,
@@ -59,20 +156,155 @@ struct Args {
     #[clap(short, long, default_value = None)]
     profile: Option<String>,
 
+    #[command(flatten)]
+    generation: GenerationArgs,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
 
+/// Generation parameters, overriding any defaults saved in the profile.
+#[derive(clap::Args, Default, Clone, Debug)]
+#[command(next_help_heading = "Generation")]
+struct GenerationArgs {
+    /// Sampling temperature; lower values give more focused replies
+    #[clap(long, default_value = None)]
+    temperature: Option<f64>,
+
+    /// Sample only from the most likely tokens within this probability mass
+    #[clap(long, default_value = None)]
+    top_p: Option<f64>,
+
+    /// Maximum number of tokens in each reply
+    #[clap(long, default_value = None)]
+    max_completion_tokens: Option<u32>,
+
+    /// Sequence at which the model stops generating; may be repeated
+    #[clap(long = "stop", default_value = None)]
+    stop: Vec<String>,
+
+    /// Seed for reproducible sampling, where the host supports it
+    #[clap(long, default_value = None)]
+    seed: Option<u64>,
+
+    /// Penalty for tokens already present, encouraging new topics
+    #[clap(long, default_value = None, allow_negative_numbers = true)]
+    presence_penalty: Option<f64>,
+
+    /// Penalty for tokens by how often they appear, discouraging repetition
+    #[clap(long, default_value = None, allow_negative_numbers = true)]
+    frequency_penalty: Option<f64>,
+
+    /// Format of the replies: text or json
+    #[clap(long, default_value = None)]
+    response_format: Option<ResponseFormat>,
+}
+
+/// Parse a server address, refusing any but HTTP(S) URLs naming a host,
+/// e.g. `localhost:8000`, which parses with `localhost` as its scheme.
+fn parse_url(text: &str) -> Result<Url, String> {
+    let url = Url::parse(text).map_err(|err| err.to_string())?;
+    match (url.scheme(), url.host_str()) {
+        ("http" | "https", Some(_)) => Ok(url),
+        ("http" | "https", None) => Err("URL has no host".to_string()),
+        _ => Err(format!(
+            "expected an http:// or https:// URL, e.g. http://{text}"
+        )),
+    }
+}
+
+/// Parse a header given as `Name: value`.
+fn parse_header(text: &str) -> Result<(String, String), String> {
+    let (name, value) = text
+        .split_once(':')
+        .ok_or("expected a header as `Name: value`")?;
+    let name = reqwest::header::HeaderName::from_str(name.trim()).map_err(|err| err.to_string())?;
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+impl From<GenerationArgs> for Parameters {
+    fn from(value: GenerationArgs) -> Self {
+        Self {
+            temperature: value.temperature,
+            top_p: value.top_p,
+            max_completion_tokens: value.max_completion_tokens,
+            stop: value.stop,
+            seed: value.seed,
+            presence_penalty: value.presence_penalty,
+            frequency_penalty: value.frequency_penalty,
+            response_format: value.response_format,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Subcommand)]
 enum ProfileCommands {
     /// Add a new profile
-    Add { name: Option<String> },
+    Add(Box<AddArgs>),
 
     /// Remove an existing profile
     Remove { name: String },
 
     /// List all profiles
     List,
+
+    /// Show the settings of a profile
+    Show { name: String },
+
+    /// Edit the settings of a profile in $EDITOR
+    Edit {
+        name: String,
+
+        /// Also enter a new API key
+        #[clap(long, default_value_t = false)]
+        key: bool,
+    },
+}
+
+#[derive(Clone, clap::Args, Debug)]
+struct AddArgs {
+    /// Name of the profile; prompted for if not given
+    name: Option<String>,
+
+    /// Host for the model; prompted for if not given
+    #[clap(long, value_enum, default_value = None)]
+    host: Option<Host>,
+
+    /// Model to use unless `--name` is given
+    #[clap(long, default_value = None)]
+    model: Option<String>,
+
+    /// API root to use instead of the host's default
+    #[clap(long, default_value = None, value_parser = parse_url)]
+    base_url: Option<Url>,
+
+    /// Address of the server, for locally served and custom models
+    #[clap(long, default_value = None, value_parser = parse_url)]
+    url: Option<Url>,
+
+    /// Header to send to a custom host, as `Name: value`; may be repeated
+    #[clap(long = "header", default_value = None, value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Seconds to wait for a custom host to reply
+    #[clap(long, default_value = None)]
+    timeout: Option<u64>,
+
+    /// TOML or JSON file describing a custom host's requests and responses
+    #[clap(long, default_value = None)]
+    spec: Option<PathBuf>,
+
+    /// System message starting each conversation unless `--system` is given
+    #[clap(long, default_value = None)]
+    system: Option<String>,
+
+    /// Where to keep the API key
+    #[clap(long, value_enum, default_value_t = SecretStore::Keyring)]
+    store: SecretStore,
+
+    /// Generation parameters to use by default with this profile
+    #[command(flatten)]
+    generation: GenerationArgs,
 }
 
 #[derive(Clone, clap::Args, Debug)]
@@ -82,16 +314,69 @@ struct ProfileArgs {
     command: ProfileCommands,
 }
 
+#[derive(Clone, clap::Args, Debug)]
+struct ExportArgs {
+    /// Transcript to export, in any transcript format
+    transcript: PathBuf,
+
+    /// Document format: markdown or html
+    #[clap(long, default_value_t = ExportFormat::Markdown)]
+    to: ExportFormat,
+
+    /// Append a summary of token usage and latency from the transcript's metadata
+    #[clap(long, default_value_t = false)]
+    usage: bool,
+
+    /// File to write the document to instead of stdout
+    #[clap(short, long, default_value = None)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, clap::Args, Debug)]
+struct AskArgs {
+    /// Prompt to send; input piped to stdin is appended to it
+    prompt: Vec<String>,
+}
+
+#[derive(Clone, clap::Args, Debug)]
+struct ModelsArgs {
+    /// Ask the host again rather than using the list cached within the last day
+    #[clap(long, default_value_t = false)]
+    refresh: bool,
+}
+
 #[derive(Subcommand, Clone)]
 enum Command {
+    /// Send a single prompt and print only the reply, for use in scripts
+    Ask(AskArgs),
+
     /// Manage profiles
     Profile(ProfileArgs),
+
+    /// Render a transcript as a Markdown or HTML document
+    Export(ExportArgs),
+
+    /// List the models offered by the selected host
+    Models(ModelsArgs),
 }
 
 impl From<Args> for ClientConfig {
     fn from(value: Args) -> Self {
         Self {
             max_tokens: value.max_tokens,
+            budget_policy: value.budget_policy,
+            tokenizer_dir: value
+                .tokenizer_dir
+                .or_else(|| std::env::var_os("AIR_TOKENIZER_DIR").map(PathBuf::from)),
+            retry: RetryPolicy {
+                max_retries: value.max_retries,
+                base_delay: Duration::from_millis(value.retry_delay),
+                max_delay: Duration::from_millis(value.max_retry_delay),
+                jitter: !value.no_retry_jitter,
+            },
+            // `/clear` starts over with the same instructions
+            retain_system: true,
+            parameters: value.generation.into(),
             verbose: value.verbose,
             ..Default::default()
         }
@@ -100,16 +385,24 @@ impl From<Args> for ClientConfig {
 
 /// Main REPL for interacting with model providers.
 fn repl<T: Write>(
-    mut client: Client,
+    client: &mut Client,
     mut transcript: Transcript<T>,
+    args: &Args,
     profile: Profile,
 ) -> Result<()> {
     const VERSION: &str = env!("CARGO_PKG_VERSION");
     println!("{} (air v{VERSION})", client);
     println!("Using profile {}", profile.name);
+    warn_estimating(client);
+    check_model(client, args, &profile);
 
-    let mut response: &Message;
-    let mut rl = DefaultEditor::new()?;
+    let mut rl = Editor::<ReplHelper, DefaultHistory>::new()?;
+    rl.set_helper(Some(ReplHelper::default()));
+    let history = profile.history_path();
+    if let Some(path) = &history {
+        // a missing file is the first session with this profile
+        let _ = rl.load_history(path);
+    }
     loop {
         let readline = rl.readline(">> ");
         match readline {
@@ -123,65 +416,548 @@ fn repl<T: Write>(
             }
             Ok(line) if line.is_empty() => continue,
             Ok(line) => {
-                let message = Message::user(line);
-                transcript.record(&message)?;
-                response = match client.send(message) {
-                    Ok(response) => response,
-                    Err(err) => {
-                        eprintln!("error: {}", err);
+                rl.add_history_entry(&line)?;
+                let outcome = match SlashCommand::parse(&line) {
+                    Some(Ok(command)) => run(command, client, &mut transcript, args, &profile),
+                    Some(Err(usage)) => {
+                        eprintln!("{usage}");
                         continue;
                     }
-                };
-                transcript.record(response)?;
-
-                // ChatGPT-style rolling output with newline formatting
-                let mut stdout = stdout().lock();
-                for line in response.content.lines() {
-                    for word in line.split_whitespace() {
-                        write!(stdout, "{} ", word)?;
-                        stdout.flush()?;
-                        sleep(Duration::from_millis(100));
+                    None => {
+                        let line = line.strip_prefix('/').unwrap_or(&line);
+                        chat(Message::user(join_lines(line)), client, &mut transcript)
                     }
+                };
+                if let Err(err) = outcome {
+                    report(err.as_ref());
+                }
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Err(err) = rl.save_history(path) {
+            eprintln!("error: could not save history to {}: {err}", path.display());
+        }
+    }
+    transcript.finish()
+}
+
+/// Send a message from the REPL, printing the response as it arrives.
+fn chat<T: Write>(
+    message: Message,
+    client: &mut Client,
+    transcript: &mut Transcript<T>,
+) -> Result<()> {
+    transcript.record_with(&message, &Metadata::now())?;
+
+    // ChatGPT-style rolling output, printing tokens as they arrive
+    let mut stdout = stdout().lock();
+    let response = client.send_stream(message, |delta| {
+        // a closed stdout only loses display; the transcript is kept
+        let _ = write!(stdout, "{delta}").and_then(|_| stdout.flush());
+    });
+    writeln!(stdout)?;
+    response?;
+    record_response(client, transcript)
+}
+
+/// Record the response ending the client's conversation with its metadata.
+fn record_response<T: Write>(client: &Client, transcript: &mut Transcript<T>) -> Result<()> {
+    let history = client.history();
+    let response = history.active().and_then(|id| history.get(id));
+    let response = &response.expect("response in history").message;
+    let metadata = client.last_metadata().cloned().unwrap_or_default();
+    transcript.record_with(response, &metadata)
+}
+
+/// Write the active branch of `history` to `sink` as a transcript, returning
+/// the number of messages written.
+fn write_branch<T: Write>(
+    history: &ConversationTree,
+    sink: &mut T,
+    format: TranscriptFormat,
+) -> Result<usize> {
+    let entries = history.entries();
+    let mut transcript = Transcript::new(sink).with_format(format);
+    for entry in &entries {
+        transcript.record_entry(entry)?;
+    }
+    transcript.finish()?;
+    Ok(entries.len())
+}
+
+/// Warn that token counts are estimates if the tokenizer for the client's
+/// model failed to load.
+fn warn_estimating(client: &Client) {
+    if let Some(err) = client.tokenizer_error() {
+        eprintln!("warning: {err:#}; estimating token counts instead");
+    }
+}
+
+/// Carry out a slash command entered at the REPL prompt.
+fn run<T: Write>(
+    command: SlashCommand,
+    client: &mut Client,
+    transcript: &mut Transcript<T>,
+    args: &Args,
+    profile: &Profile,
+) -> Result<()> {
+    match command {
+        SlashCommand::Clear => client.clear(),
+        SlashCommand::System(text) => {
+            transcript.record_with(&Message::system(&text), &Metadata::now())?;
+            client.set_system(text);
+        }
+        SlashCommand::Model(name) => {
+            let args = Args {
+                name: Some(name),
+                ..args.clone()
+            };
+            *client = connect(&args, profile)?.continuing(client);
+            warn_estimating(client);
+            check_model(client, &args, profile);
+            println!("{client}");
+        }
+        SlashCommand::Edit(draft) => {
+            let message = open_editor(&draft)?;
+            match message.trim() {
+                "" => println!("Nothing to send"),
+                _ => chat(Message::user(message.trim_end()), client, transcript)?,
+            }
+        }
+        SlashCommand::Save(path) => {
+            let mut file = File::create(&path)?;
+            // text and JSON Lines hold a single branch; otherwise keep them all
+            let saved = match TranscriptFormat::from_path(&path) {
+                Some(format @ (TranscriptFormat::Text | TranscriptFormat::JsonLines)) => {
+                    write_branch(client.history(), &mut file, format)?
+                }
+                _ => {
+                    save_tree(client.history(), &mut file)?;
+                    client.history().len()
                 }
-                writeln!(stdout)?;
+            };
+            println!("Saved {saved} messages to {}", path.display());
+        }
+        SlashCommand::Load(path) => {
+            client.set_history(load_tree(File::open(&path)?)?);
+            println!("Loaded {} messages", client.history().len());
+        }
+        SlashCommand::Undo => {
+            if !client.undo() {
+                println!("Nothing to undo");
+            }
+        }
+        SlashCommand::Retry => {
+            let response = client.regenerate()?;
+            println!("{}", response.content);
+            record_response(client, transcript)?;
+        }
+        SlashCommand::Tokens => {
+            match client.tokens_sent() {
+                Some(sent) => println!("{sent} tokens sent"),
+                None => println!("Tokens sent are unknown for this provider"),
+            }
+            println!("Context is ~{} tokens", client.count_tokens());
+        }
+        SlashCommand::History => {
+            for message in client.history().messages() {
+                println!("{}: {}", message.role, message.content);
             }
         }
+        SlashCommand::Help => println!("{}", SlashCommand::help()),
     }
+    Ok(())
+}
+
+/// Answer a single prompt without the REPL, printing only the reply. A
+/// provider error is reported on stderr and sets the exit status.
+fn ask<T: Write>(
+    mut client: Client,
+    mut transcript: Transcript<T>,
+    prompt: String,
+) -> Result<ExitCode> {
+    let message = Message::user(prompt);
+    let status = match message.content.trim() {
+        "" => Err(ProviderError::NoPrompt),
+        _ => {
+            transcript.record_with(&message, &Metadata::now())?;
+            client
+                .send(message)
+                .map(|response| println!("{}", response.content))
+        }
+    };
+    let status = match status {
+        Ok(()) => {
+            record_response(&client, &mut transcript)?;
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            report(&err);
+            ExitCode::from(err.exit_code())
+        }
+    };
+    transcript.finish()?;
+    Ok(status)
+}
 
+/// Print `err`, with a hint at the setting to change if it is a provider
+/// error the command line can address.
+fn report(err: &(dyn std::error::Error + Send + Sync + 'static)) {
+    eprintln!("error: {err}");
+    let hint = match err.downcast_ref::<ProviderError>() {
+        Some(ProviderError::AuthenticationFailed { .. }) => {
+            "check the API key of the selected profile or `API_KEY`"
+        }
+        Some(ProviderError::ContextLengthExceeded(_)) => {
+            "clear the context or lower `--max-tokens`"
+        }
+        _ => return,
+    };
+    eprintln!("hint: {hint}");
+}
+
+/// The prompt for one-shot use: the words given to `ask`, followed by
+/// anything piped to stdin.
+fn one_shot_prompt(words: &[String]) -> Result<String> {
+    let mut parts = vec![words.join(" ")];
+    if !std::io::stdin().is_terminal() {
+        let mut piped = String::new();
+        std::io::stdin().read_to_string(&mut piped)?;
+        parts.push(piped);
+    }
+    parts.retain(|part| !part.trim().is_empty());
+    Ok(parts.join("\n\n"))
+}
+
+/// Read an API key without echoing it.
+fn prompt_key() -> Result<String> {
+    Ok(Password::new("Enter API key: ")
+        .with_display_mode(inquire::PasswordDisplayMode::Masked)
+        .without_confirmation()
+        .prompt()?)
+}
+
+/// Answer to an optional prompt, `None` if left empty.
+fn optional(prompt: Text) -> Result<Option<String>> {
+    let answer = prompt.prompt()?;
+    Ok(Some(answer.trim().to_string()).filter(|answer| !answer.is_empty()))
+}
+
+/// Check an address typed at a prompt with `parse_url`; empty answers pass.
+fn validate_url(text: &str) -> Result<Validation, inquire::CustomUserError> {
+    Ok(match text.trim() {
+        "" => Validation::Valid,
+        text => match parse_url(text) {
+            Ok(_) => Validation::Valid,
+            Err(err) => Validation::Invalid(err.into()),
+        },
+    })
+}
+
+/// Read a custom host's spec from a JSON file, or TOML for any other
+/// extension, checking its placeholders and pointers.
+fn load_spec(path: &Path) -> Result<CustomSpec> {
+    let text = std::fs::read_to_string(path)?;
+    let spec: CustomSpec = match path.extension().is_some_and(|ext| ext == "json") {
+        true => serde_json::from_str(&text)?,
+        false => config::from_toml(&text)?,
+    };
+    spec.check()
+        .map_err(|err| anyhow!("{}: {err}", path.display()))?;
+    Ok(spec)
+}
+
+/// Create a client for the model selected by `args`, using the credentials
+/// and settings of `profile`.
+fn connect(args: &Args, profile: &Profile) -> Result<Client> {
+    let mut config = ClientConfig::from(args.clone());
+    config.parameters = config.parameters.or(&profile.parameters);
+    let base_url = args
+        .base_url
+        .as_ref()
+        .map(Url::to_string)
+        .or(profile.base_url.clone());
+    let host = args.host.or(profile.host).unwrap_or(Host::OpenAI);
+    let name = args.name.clone().or(profile.model.clone());
+    let default_name = || host.default_model().unwrap_or_default().to_string();
+    let url = match (&args.url, &profile.url) {
+        (Some(url), _) => Some(url.clone()),
+        (None, Some(url)) => Some(parse_url(url).map_err(|err| anyhow!("{url}: {err}"))?),
+        (None, None) => None,
+    };
+    let client = match host {
+        Host::OpenAI => {
+            let name = name.unwrap_or_else(default_name);
+            let provider = match base_url {
+                Some(url) => OpenAI::new(name, profile.key.clone()).with_base_url(url),
+                None => OpenAI::new(name, profile.key.clone()),
+            };
+            Client::new(provider)
+        }
+        Host::Anthropic => {
+            let name = name.unwrap_or_else(default_name);
+            let provider = match base_url {
+                Some(url) => Anthropic::new(name, profile.key.clone()).with_base_url(url),
+                None => Anthropic::new(name, profile.key.clone()),
+            };
+            Client::new(provider)
+        }
+        Host::Ollama => {
+            let name = name.unwrap_or_else(default_name);
+            let url = match url {
+                Some(url) => url,
+                None => Url::from_str(Ollama::DEFAULT_URL)?,
+            };
+            Client::new(Ollama::new(name, url))
+        }
+        Host::LlamaCpp => {
+            let url = match url {
+                Some(url) => url,
+                None => Url::from_str(LlamaCpp::DEFAULT_URL)?,
+            };
+            let provider = match name {
+                Some(name) => LlamaCpp::new(url).with_model(name),
+                None => LlamaCpp::new(url),
+            };
+            Client::new(provider)
+        }
+        Host::Custom => {
+            let Some(url) = url else {
+                bail!("A custom host needs its address, given by `--url` or the profile");
+            };
+            let mut provider = Custom::new(url);
+            if let Some(name) = name {
+                provider = provider.with_model(name);
+            }
+            if let Some(path) = args.spec.as_ref().or(profile.spec.as_ref()) {
+                provider = provider.with_spec(load_spec(path)?);
+            }
+            if !profile.key.is_empty() {
+                provider = provider.with_bearer_auth(&profile.key)?;
+            }
+            // later headers replace earlier ones: flags override the profile,
+            // and an explicit `Authorization` the key
+            let headers = profile.headers.clone().into_iter();
+            for (name, value) in headers.chain(args.headers.clone()) {
+                provider = provider.with_header(name, value)?;
+            }
+            if let Some(seconds) = args.timeout.or(profile.timeout) {
+                provider = provider.with_timeout(Duration::from_secs(seconds));
+            }
+            Client::new(provider)
+        }
+    }
+    .with(config);
+    Ok(client)
+}
+
+/// The models offered by the host `client` talks to, cached per profile,
+/// address and spec for `models::TTL`.
+fn list_models(
+    client: &Client,
+    args: &Args,
+    profile: &Profile,
+    refresh: bool,
+) -> Result<Vec<String>> {
+    ModelCache::open()?.models(&cache_key(args, profile), models::TTL, refresh, || {
+        Ok(client.models()?)
+    })
+}
+
+/// Identifies the host selected by `args` and `profile` in the model cache.
+fn cache_key(args: &Args, profile: &Profile) -> String {
+    let host = args.host.or(profile.host).unwrap_or(Host::OpenAI);
+    let address = [&args.url, &args.base_url]
+        .into_iter()
+        .flatten()
+        .map(Url::to_string)
+        .chain(profile.url.clone())
+        .chain(profile.base_url.clone())
+        .next()
+        .unwrap_or_default();
+    let spec = args.spec.as_ref().or(profile.spec.as_ref());
+    let spec = spec.map(|path| path.display().to_string());
+    let key = format!(
+        "{} {host} {address} {}",
+        profile.name,
+        spec.unwrap_or_default()
+    );
+    key.trim_end().to_string()
+}
+
+/// Warn when the host does not list the model named by `--name` or the
+/// profile, suggesting the one most likely meant. The list is fetched unless
+/// cached; a host that cannot list its models is not checked.
+fn check_model(client: &Client, args: &Args, profile: &Profile) {
+    let Some(name) = args.name.as_ref().or(profile.model.as_ref()) else {
+        return;
+    };
+    let models = match list_models(client, args, profile, false) {
+        Ok(models) if !models.is_empty() => models,
+        _ => return,
+    };
+    if models::is_listed(name, &models) {
+        return;
+    }
+    match models::suggest(name, &models) {
+        Some(model) => eprintln!("warning: no model {name} is listed; did you mean {model}?"),
+        None => eprintln!("warning: no model {name} is listed; see `air models`"),
+    }
+}
+
+/// Render a saved transcript as a document for sharing.
+fn export(args: &ExportArgs) -> Result<()> {
+    let entries = load_entries(File::open(&args.transcript)?)?;
+    let messages: Vec<Message> = entries.iter().map(|entry| entry.message.clone()).collect();
+    let usage = args.usage.then(|| UsageSummary::from_entries(&entries));
+    let document = render(&messages, args.to, usage.as_ref());
+    match &args.output {
+        Some(path) => std::fs::write(path, document)?,
+        None => print!("{document}"),
+    }
     Ok(())
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
 
+    if let Some(Command::Export(ref export_args)) = args.command {
+        export(export_args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // answer a single prompt when asked to, or when input is piped in
+    let prompt = match args.command {
+        Some(Command::Ask(ref ask_args)) => Some(one_shot_prompt(&ask_args.prompt)?),
+        None if !std::io::stdin().is_terminal() => Some(one_shot_prompt(&[])?),
+        _ => None,
+    };
+
     // handle profile commands
     if let Some(Command::Profile(profile_args)) = args.command {
         match profile_args.command {
-            ProfileCommands::Add { name } => {
+            ProfileCommands::Add(add_args) => {
+                let AddArgs {
+                    name,
+                    host,
+                    model,
+                    base_url,
+                    url,
+                    headers,
+                    timeout,
+                    spec,
+                    system,
+                    store,
+                    generation,
+                } = *add_args;
+                let name = match name {
+                    Some(name) => name,
+                    None => Text::new("Enter profile name: ").prompt()?,
+                };
+                let host = match host {
+                    Some(host) => host,
+                    None => {
+                        let hosts = Host::value_variants().to_vec();
+                        let openai = hosts.iter().position(|h| *h == Host::OpenAI);
+                        Select::new("Select host: ", hosts)
+                            .with_starting_cursor(openai.unwrap_or_default())
+                            .prompt()?
+                    }
+                };
+                // a custom host may take a bearer token; left empty, none is sent
+                let key = match host.requires_key() || host == Host::Custom {
+                    true => prompt_key()?,
+                    false => String::new(),
+                };
+                let model = match model {
+                    Some(model) => Some(model),
+                    None => {
+                        let default = host.default_model().unwrap_or("the server's");
+                        optional(Text::new("Default model: ").with_placeholder(default))?
+                    }
+                };
+                let base_url = match base_url {
+                    Some(url) => Some(url.to_string()),
+                    None if matches!(host, Host::OpenAI | Host::Anthropic) => {
+                        let text = Text::new("API root: ")
+                            .with_placeholder("the host's default")
+                            .with_validator(validate_url);
+                        optional(text)?
+                    }
+                    None => None,
+                };
+                let url = match url {
+                    Some(url) => Some(url.to_string()),
+                    None if host == Host::Custom => {
+                        let text = Text::new("Server address: ")
+                            .with_placeholder("http://localhost:8000/chat")
+                            .with_validator(validate_url);
+                        optional(text)?
+                    }
+                    None => None,
+                };
+                let system = match system {
+                    Some(system) => Some(system),
+                    None => optional(Text::new("System message: ").with_placeholder("none"))?,
+                };
                 let profile = Profile {
-                    name: name
-                        .unwrap_or_else(|| Text::new("Enter profile name: ").prompt().unwrap()),
-                    key: Password::new("Enter API key: ")
-                        .with_display_mode(inquire::PasswordDisplayMode::Masked)
-                        .without_confirmation()
-                        .prompt()?,
+                    name,
+                    key,
+                    host: Some(host),
+                    model,
+                    base_url,
+                    url,
+                    headers: headers.into_iter().collect(),
+                    timeout,
+                    // kept wherever air is run from
+                    spec: spec.map(std::path::absolute).transpose()?,
+                    system,
+                    store,
+                    parameters: generation.into(),
+                    ..Default::default()
                 };
-                profile.save().expect("Failed to save profile");
+                profile.save()?;
                 println!("Created profile {}", profile.name);
-                return Ok(());
+                return Ok(ExitCode::SUCCESS);
             }
             ProfileCommands::Remove { name } => {
-                let profile = Profile::load(name.clone())?;
-                profile.delete().expect("Failed to remove profile");
+                Profiles::open()?.remove(&name)?;
                 println!("Removed profile {}", name);
-                return Ok(());
+                return Ok(ExitCode::SUCCESS);
             }
             ProfileCommands::List => {
-                let profiles = Profile::list().expect("Failed to list profiles");
+                let profiles = Profile::list()?;
                 for profile in profiles {
-                    println!("{}", profile.name);
+                    let host = profile.host.map(|host| host.to_string());
+                    match [host, profile.model]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                    {
+                        settings if settings.is_empty() => println!("{}", profile.name),
+                        settings => println!("{:<16}{}", profile.name, settings.join(" ")),
+                    }
                 }
-                return Ok(());
+                return Ok(ExitCode::SUCCESS);
+            }
+            ProfileCommands::Show { name } => {
+                print!("{}", Profile::load(name)?.to_toml()?);
+                return Ok(ExitCode::SUCCESS);
+            }
+            ProfileCommands::Edit { name, key } => {
+                let profile = Profile::load(name)?;
+                let mut edited = profile.with_toml(&open_editor(&profile.to_toml()?)?)?;
+                if key {
+                    edited.key = prompt_key()?;
+                }
+                edited.save()?;
+                println!("Updated profile {}", edited.name);
+                return Ok(ExitCode::SUCCESS);
             }
         }
     }
@@ -189,50 +965,92 @@ fn main() -> Result<()> {
     // otherwise load profile from args or environment
     let profile = match args.profile {
         None => {
-            if dotenv().is_ok() {
+            // outside the REPL, stdout carries only the reply or listing
+            if dotenv().is_ok() && args.command.is_none() && prompt.is_none() {
                 println!("Loaded .env file");
             };
-            let key = std::env::var("API_KEY").expect(
-                "No credentials found. You must select an existing profile or set the environment variable `API_KEY`",
-                );
+            let host = args.host.unwrap_or(Host::OpenAI);
+            let key = match std::env::var("API_KEY") {
+                Ok(key) => key,
+                Err(_) if !host.requires_key() => String::new(),
+                Err(_) => {
+                    let missing = "No credentials found. You must select an existing profile or set the environment variable `API_KEY`";
+                    if prompt.is_none() {
+                        bail!(missing);
+                    }
+                    // scripts see the same status as for a rejected key
+                    eprintln!("error: {missing}");
+                    let err = ProviderError::AuthenticationFailed { error: None };
+                    return Ok(ExitCode::from(err.exit_code()));
+                }
+            };
             Profile {
                 name: "from environment".to_string(),
                 key,
+                ..Default::default()
             }
         }
         Some(ref name) => Profile::load(name.clone())?,
     };
 
-    let context = match args.input {
-        None => Vec::new(),
+    let history = match args.input {
+        None => Default::default(),
         Some(ref path) => {
             let file = File::open(path)?;
-            load(file)?
+            load_tree(file)?
         }
     };
 
-    // create client based on args, key, context, etc.
-    let client = match args.host {
-        Host::OpenAI => {
-            let name = args.name.clone().unwrap_or("gpt-3.5-turbo".to_string());
-            let provider = OpenAI::new(name, profile.key.clone());
-            Client::new(provider)
-        }
-        Host::Custom => {
-            let provider = Custom::new(Url::from_str("localhost:8000")?);
-            Client::new(provider)
+    let mut client = connect(&args, &profile)?.with_history(history);
+    if let Some(Command::Models(ref models_args)) = args.command {
+        for model in list_models(&client, &args, &profile, models_args.refresh)? {
+            println!("{model}");
         }
+        return Ok(ExitCode::SUCCESS);
+    }
+    let system = match (&args.system, &args.system_file) {
+        (Some(text), _) => Some(text.clone()),
+        (None, Some(path)) => Some(std::fs::read_to_string(path)?),
+        // the profile's applies unless the transcript opens with its own
+        (None, None) => match client.history().messages().first() {
+            Some(message) if message.role == Role::System => None,
+            _ => profile.system.clone(),
+        },
+    };
+    let system = system.map(|text| Message::system(text.trim_end()));
+    if let Some(ref system) = system {
+        client.set_system(system.content.clone());
     }
-    .with(args.clone().into())
-    .with_context(context);
 
     // setup transcript to record on calls to `record` if output is provided
+    let format = args
+        .format
+        .or_else(|| args.output.as_deref().and_then(TranscriptFormat::from_path))
+        .unwrap_or_default();
     let mut writer: Option<LineWriter<_>> = args
         .output
+        .as_ref()
         .map(File::create)
         .transpose()?
         .map(LineWriter::new);
-    let transcript = Transcript::conditionally(writer.as_mut());
+    let mut transcript = Transcript::conditionally(writer.as_mut()).with_format(format);
+    if let Some(ref system) = system {
+        transcript.record_with(system, &Metadata::now())?;
+    }
 
-    repl(client, transcript, profile)
+    match prompt {
+        Some(prompt) => ask(client, transcript, prompt),
+        None => {
+            repl(&mut client, transcript, &args, profile)?;
+            // recorded as it happened, the output still holds what /undo,
+            // /retry, /clear and /load discarded; leave the conversation kept
+            if let Some(writer) = writer {
+                let mut file = writer.into_inner()?;
+                file.set_len(0)?;
+                file.rewind()?;
+                write_branch(client.history(), &mut file, format)?;
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+    }
 }
@@ -0,0 +1,186 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config;
+
+/// How long a fetched model list is used before asking the host again.
+pub const TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct Listing {
+    fetched: DateTime<Utc>,
+    models: Vec<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Listings {
+    #[serde(default)]
+    hosts: BTreeMap<String, Listing>,
+}
+
+/// Model lists fetched from hosts, kept on disk to spare a request each run.
+pub struct ModelCache {
+    path: PathBuf,
+}
+
+impl ModelCache {
+    /// The cache in the user's cache directory, see `config::cache_dir`.
+    pub fn open() -> Result<Self> {
+        let dir = config::cache_dir()
+            .ok_or_else(|| anyhow!("No cache directory found; set XDG_CACHE_HOME or HOME"))?;
+        Ok(Self::at(dir.join("models.toml")))
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The models of the host identified by `key`, fetched with `fetch` if
+    /// the cached list is older than `ttl` or a refresh is asked for.
+    pub fn models<F>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        refresh: bool,
+        fetch: F,
+    ) -> Result<Vec<String>>
+    where
+        F: FnOnce() -> Result<Vec<String>>,
+    {
+        let mut listings = self.listings();
+        if let Some(models) = listings.fresh(key, ttl).filter(|_| !refresh) {
+            return Ok(models);
+        }
+        let models = fetch()?;
+        let listing = Listing {
+            fetched: Utc::now(),
+            models: models.clone(),
+        };
+        listings.hosts.insert(key.to_string(), listing);
+        // the list is still good for this run
+        if let Err(err) = config::write(&self.path, &listings, false) {
+            eprintln!(
+                "warning: could not cache models in {}: {err}",
+                self.path.display()
+            );
+        }
+        Ok(models)
+    }
+
+    /// The cached listings; a cache that cannot be read is treated as empty,
+    /// to be replaced by the next listing fetched.
+    fn listings(&self) -> Listings {
+        config::read(&self.path).unwrap_or_default()
+    }
+}
+
+impl Listings {
+    /// The models listed for `key`, unless fetched longer than `ttl` ago.
+    fn fresh(&self, key: &str, ttl: Duration) -> Option<Vec<String>> {
+        let listing = self.hosts.get(key)?;
+        let age = (Utc::now() - listing.fetched).to_std().unwrap_or_default();
+        (age < ttl).then(|| listing.models.clone())
+    }
+}
+
+/// Whether `name` is one of `models`, allowing Ollama's implied `:latest`.
+pub fn is_listed(name: &str, models: &[String]) -> bool {
+    models
+        .iter()
+        .any(|model| model == name || model.strip_suffix(":latest") == Some(name))
+}
+
+/// The model in `models` closest to `name`, if any is close enough to be a
+/// likely typo.
+pub fn suggest<'a>(name: &str, models: &'a [String]) -> Option<&'a str> {
+    let threshold = (name.chars().count() / 3).max(2);
+    models
+        .iter()
+        .map(|model| (distance(name, model), model))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, model)| model.as_str())
+}
+
+/// Levenshtein distance between two strings, by characters.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(models: &[&str]) -> Vec<String> {
+        models.iter().map(|model| model.to_string()).collect()
+    }
+
+    #[test]
+    fn test_suggest_close_models() {
+        let models = names(&["gpt-4o", "gpt-4o-mini", "gpt-3.5-turbo"]);
+        assert_eq!(suggest("gpt4o", &models), Some("gpt-4o"));
+        assert_eq!(suggest("gpt-3.5-trubo", &models), Some("gpt-3.5-turbo"));
+        assert_eq!(suggest("llama3", &models), None);
+        assert!(is_listed("llama3", &names(&["llama3:latest"])));
+        assert!(!is_listed("llama3", &names(&["llama3:8b"])));
+    }
+
+    #[test]
+    fn test_cache_until_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ModelCache::at(dir.path().join("models.toml"));
+        let fetch = || Ok(names(&["small", "large"]));
+        assert_eq!(cache.models("custom", TTL, false, fetch).unwrap().len(), 2);
+
+        let unreachable = || Err(anyhow!("fetched again"));
+        assert_eq!(
+            cache
+                .models("custom", TTL, false, unreachable)
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(cache.models("custom", TTL, true, unreachable).is_err());
+        assert!(cache
+            .models("custom", Duration::ZERO, false, unreachable)
+            .is_err());
+        assert!(cache.models("ollama", TTL, false, unreachable).is_err());
+    }
+
+    #[test]
+    fn test_cache_recovers_from_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("models.toml");
+        std::fs::write(&path, "hosts = [not toml").unwrap();
+        let cache = ModelCache::at(path);
+        let unreachable = || Err(anyhow!("fetched again"));
+        assert!(cache.models("custom", TTL, false, unreachable).is_err());
+        let fetch = || Ok(names(&["small"]));
+        assert_eq!(cache.models("custom", TTL, false, fetch).unwrap().len(), 1);
+        assert_eq!(
+            cache
+                .models("custom", TTL, false, unreachable)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // a cache that cannot be written still answers
+        let cache = ModelCache::at(dir.path().join("models.toml").join("nested"));
+        assert_eq!(cache.models("custom", TTL, false, fetch).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,369 @@
+use air::Parameters;
+use anyhow::{anyhow, Result};
+use keyring::Entry;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::{config, Host};
+
+mod secret;
+pub use secret::SecretStore;
+
+/// Named credentials and settings. Settings are recorded in the profile
+/// index, `profiles.toml` in the config directory, and the API key, if the
+/// profile has one, in the profile's secret store.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(skip)]
+    pub name: String,
+
+    #[serde(skip)]
+    pub key: String,
+
+    /// Host used unless `--host` is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<Host>,
+
+    /// Model used unless `--name` is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    /// API root to use instead of the provider's default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+
+    /// Address of the server, for locally served and custom models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// Headers sent to a custom host unless `--header` gives one of the same name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    /// Seconds to wait for a custom host to reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+
+    /// File describing a custom host's requests and responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spec: Option<PathBuf>,
+
+    /// System message starting each conversation unless `--system` is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+
+    /// Where the key is kept
+    #[serde(default)]
+    pub store: SecretStore,
+
+    /// Set when the profile has no key, e.g. for Ollama, so that no store
+    /// needs to be available to load it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keyless: bool,
+
+    /// Generation parameters used unless overridden by flags
+    #[serde(default)]
+    pub parameters: Parameters,
+}
+
+/// Profiles saved before the index, kept whole as a keyring secret.
+#[derive(Deserialize)]
+struct Legacy {
+    key: String,
+    base_url: Option<String>,
+    #[serde(default)]
+    parameters: Parameters,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Index {
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// The profiles saved under a config directory.
+pub struct Profiles {
+    dir: PathBuf,
+
+    /// Passphrase for encrypted keys, asked for when needed if `None`
+    passphrase: Option<String>,
+}
+
+impl Profiles {
+    /// Profiles in the user's config directory, see `config::config_dir`.
+    pub fn open() -> Result<Self> {
+        let dir = config::config_dir()
+            .ok_or_else(|| anyhow!("No config directory found; set XDG_CONFIG_HOME or HOME"))?;
+        Ok(Self::at(dir))
+    }
+
+    pub fn at(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            passphrase: None,
+        }
+    }
+
+    /// Open encrypted keys with `passphrase` rather than asking for it.
+    #[cfg(test)]
+    pub(crate) fn with_passphrase(mut self, passphrase: &str) -> Self {
+        self.passphrase = Some(passphrase.to_string());
+        self
+    }
+
+    fn index(&self) -> Result<Index> {
+        config::read(&self.dir.join("profiles.toml"))
+    }
+
+    fn write_index(&self, index: &Index) -> Result<()> {
+        config::write(&self.dir.join("profiles.toml"), index, false)
+    }
+
+    /// Load a profile and its key. A profile saved before the index is
+    /// added to it, so that it is listed from then on.
+    pub fn load(&self, name: &str) -> Result<Profile> {
+        let Some(profile) = self.index()?.profiles.remove(name) else {
+            let profile = legacy(name)?;
+            if let Err(err) = self.save(&profile) {
+                eprintln!("warning: could not add profile {name} to the index: {err}");
+            }
+            return Ok(profile);
+        };
+        let key = match profile.keyless {
+            true => String::new(),
+            false => profile
+                .store
+                .get(&self.dir, name, self.passphrase.as_deref())?,
+        };
+        Ok(Profile {
+            name: name.to_string(),
+            key,
+            ..profile
+        })
+    }
+
+    /// All indexed profiles by name, without their keys.
+    pub fn list(&self) -> Result<Vec<Profile>> {
+        let profiles = self.index()?.profiles.into_iter();
+        Ok(profiles
+            .map(|(name, profile)| Profile { name, ..profile })
+            .collect())
+    }
+
+    /// Save a profile, replacing any of the same name. The key is stored
+    /// first, so a failing secret store leaves the index unchanged, and is
+    /// removed from a previous store only once the index no longer uses it.
+    /// A profile without a key is saved without touching any store.
+    pub fn save(&self, profile: &Profile) -> Result<()> {
+        let mut index = self.index()?;
+        let keyless = profile.key.is_empty();
+        if !keyless {
+            let passphrase = self.passphrase.as_deref();
+            profile
+                .store
+                .set(&self.dir, &profile.name, &profile.key, passphrase)?;
+        }
+        let saved = Profile {
+            keyless,
+            ..profile.clone()
+        };
+        let previous = index.profiles.insert(profile.name.clone(), saved);
+        self.write_index(&index)?;
+        let keyring = SecretStore::Keyring;
+        match previous.filter(|previous| !previous.keyless) {
+            Some(old) if keyless => old.store.delete(&self.dir, &profile.name),
+            // file and encrypted keys share an entry, which now holds the new key
+            Some(Profile { store: old, .. })
+                if old != profile.store && (old == keyring || profile.store == keyring) =>
+            {
+                old.delete(&self.dir, &profile.name)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove a profile and its key.
+    pub fn remove(&self, name: &str) -> Result<()> {
+        let mut index = self.index()?;
+        let Some(profile) = index.profiles.remove(name) else {
+            return Ok(Entry::new("air", name)?.delete_password()?);
+        };
+        if !profile.keyless {
+            profile.store.delete(&self.dir, name)?;
+        }
+        self.write_index(&index)
+    }
+}
+
+/// Load a profile saved to the keyring before the index existed.
+fn legacy(name: &str) -> Result<Profile> {
+    let secret = Entry::new("air", name)?.get_password()?;
+    // the earliest profiles hold only the key
+    let legacy = serde_json::from_str::<Legacy>(&secret).unwrap_or(Legacy {
+        key: secret,
+        base_url: None,
+        parameters: Parameters::default(),
+    });
+    Ok(Profile {
+        name: name.to_string(),
+        key: legacy.key,
+        base_url: legacy.base_url,
+        store: SecretStore::Keyring,
+        parameters: legacy.parameters,
+        ..Default::default()
+    })
+}
+
+impl Profile {
+    pub fn load(name: String) -> Result<Self> {
+        Profiles::open()?.load(&name)
+    }
+
+    /// File keeping the REPL's input history for this profile, under
+    /// `$XDG_STATE_HOME/air/history` (`~/.local/state` by default).
+    pub fn history_path(&self) -> Option<PathBuf> {
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        Some(config::state_dir()?.join("history").join(name))
+    }
+
+    pub fn list() -> Result<Vec<Self>> {
+        Profiles::open()?.list()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        Profiles::open()?.save(self)
+    }
+
+    /// The profile's settings as TOML, without its key.
+    pub fn to_toml(&self) -> Result<String> {
+        let settings = config::to_toml(self)?;
+        Ok(format!("# Settings of profile {}\n{settings}", self.name))
+    }
+
+    /// This profile with the settings in `text`, as written by `to_toml`.
+    pub fn with_toml(&self, text: &str) -> Result<Self> {
+        let settings: Profile = config::from_toml(text)?;
+        Ok(Profile {
+            name: self.name.clone(),
+            key: self.key.clone(),
+            ..settings
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str) -> Profile {
+        Profile {
+            name: name.to_string(),
+            key: format!("sk-{name}"),
+            host: Some(Host::OpenAI),
+            model: Some("gpt-4o".to_string()),
+            base_url: Some("http://localhost:4000/v1".to_string()),
+            system: Some("Answer briefly.".to_string()),
+            store: SecretStore::File,
+            parameters: Parameters {
+                temperature: Some(0.5),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_profiles_stay_in_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles = Profiles::at(dir.path().to_path_buf());
+        profiles.save(&profile("work")).unwrap();
+        profiles.save(&profile("home")).unwrap();
+
+        let names: Vec<_> = profiles
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, vec!["home", "work"]);
+
+        let work = profiles.load("work").unwrap();
+        assert_eq!(work.key, "sk-work");
+        assert_eq!(work.host, Some(Host::OpenAI));
+        assert_eq!(work.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(work.parameters.temperature, Some(0.5));
+
+        // the index holds settings only
+        let index = std::fs::read_to_string(dir.path().join("profiles.toml")).unwrap();
+        assert!(index.contains("[profiles.work]"), "{index}");
+        assert!(!index.contains("sk-work"), "{index}");
+
+        profiles.remove("work").unwrap();
+        assert_eq!(profiles.list().unwrap().len(), 1);
+        assert!(SecretStore::File.get(dir.path(), "work", None).is_err());
+    }
+
+    #[test]
+    fn test_save_changes_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles = Profiles::at(dir.path().to_path_buf()).with_passphrase("correct horse");
+        profiles.save(&profile("work")).unwrap();
+
+        let encrypted = Profile {
+            store: SecretStore::Encrypted,
+            ..profile("work")
+        };
+        profiles.save(&encrypted).unwrap();
+        assert_eq!(profiles.load("work").unwrap().key, "sk-work");
+    }
+
+    #[test]
+    fn test_keyless_profile_skips_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles = Profiles::at(dir.path().to_path_buf());
+        profiles.save(&profile("local")).unwrap();
+
+        // saved without the keyring, and the key it replaces is removed
+        let local = Profile {
+            key: String::new(),
+            host: Some(Host::Ollama),
+            store: SecretStore::Keyring,
+            ..profile("local")
+        };
+        profiles.save(&local).unwrap();
+        assert!(SecretStore::File.get(dir.path(), "local", None).is_err());
+
+        let loaded = profiles.load("local").unwrap();
+        assert_eq!(loaded.key, "");
+        assert!(loaded.keyless);
+        profiles.remove("local").unwrap();
+        assert!(profiles.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_edit_settings_as_toml() {
+        let work = profile("work");
+        let text = work.to_toml().unwrap();
+        assert!(text.contains("host = \"open-ai\""), "{text}");
+        assert!(!text.contains("sk-work"), "{text}");
+
+        let edited = text.replace("gpt-4o", "gpt-4o-mini").replace("0.5", "0.2");
+        let edited = work.with_toml(&edited).unwrap();
+        assert_eq!(edited.name, "work");
+        assert_eq!(edited.key, "sk-work");
+        assert_eq!(edited.model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(edited.system, work.system);
+        assert_eq!(edited.parameters.temperature, Some(0.2));
+    }
+}
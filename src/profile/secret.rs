@@ -0,0 +1,194 @@
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use inquire::Password;
+use keyring::Entry;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+use std::path::Path;
+
+use crate::config;
+
+/// Where a profile's API key is kept.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SecretStore {
+    /// The system keyring, e.g. Secret Service or the macOS Keychain
+    #[default]
+    Keyring,
+
+    /// A plaintext file readable only by its owner, for machines without a keyring
+    File,
+
+    /// A file encrypted with a passphrase, taken from `AIR_PASSPHRASE` or prompted for
+    Encrypted,
+}
+
+/// Keys kept in `credentials.toml` by the file-backed stores, by profile.
+#[derive(Default, Serialize, Deserialize)]
+struct Credentials {
+    #[serde(default)]
+    keys: BTreeMap<String, String>,
+}
+
+const CREDENTIALS: &str = "credentials.toml";
+
+/// PBKDF2 rounds deriving the encryption key from a passphrase.
+const ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+
+impl SecretStore {
+    /// Read the key of profile `name`, with file-backed stores under `dir`.
+    /// The encrypted store uses `passphrase`, or asks for one if `None`.
+    pub fn get(self, dir: &Path, name: &str, passphrase: Option<&str>) -> Result<String> {
+        if self == SecretStore::Keyring {
+            return Ok(Entry::new("air", name)?.get_password()?);
+        }
+        let credentials: Credentials = config::read(&dir.join(CREDENTIALS))?;
+        let secret = credentials
+            .keys
+            .get(name)
+            .ok_or_else(|| anyhow!("no key stored for profile {name}"))?;
+        match self {
+            SecretStore::Encrypted => decrypt(secret, &ask_passphrase(passphrase, false)?),
+            _ => Ok(secret.clone()),
+        }
+    }
+
+    pub fn set(self, dir: &Path, name: &str, key: &str, passphrase: Option<&str>) -> Result<()> {
+        let secret = match self {
+            SecretStore::Keyring => {
+                return Entry::new("air", name)?.set_password(key).context(
+                    "Could not use the system keyring; try `--store file` or `--store encrypted`",
+                );
+            }
+            SecretStore::File => key.to_string(),
+            SecretStore::Encrypted => encrypt(key, &ask_passphrase(passphrase, true)?)?,
+        };
+        let path = dir.join(CREDENTIALS);
+        let mut credentials: Credentials = config::read(&path)?;
+        credentials.keys.insert(name.to_string(), secret);
+        config::write(&path, &credentials, true)
+    }
+
+    /// Remove the key of profile `name`; a key already missing is not an error.
+    pub fn delete(self, dir: &Path, name: &str) -> Result<()> {
+        if self == SecretStore::Keyring {
+            return match Entry::new("air", name)?.delete_password() {
+                Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+                Err(err) => Err(err.into()),
+            };
+        }
+        let path = dir.join(CREDENTIALS);
+        let mut credentials: Credentials = config::read(&path)?;
+        if credentials.keys.remove(name).is_some() {
+            config::write(&path, &credentials, true)?;
+        }
+        Ok(())
+    }
+}
+
+/// The passphrase given, else the one in `AIR_PASSPHRASE`, else one the user
+/// enters.
+fn ask_passphrase(given: Option<&str>, confirm: bool) -> Result<String> {
+    if let Some(passphrase) = given {
+        return Ok(passphrase.to_string());
+    }
+    if let Ok(passphrase) = std::env::var("AIR_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let prompt = Password::new("Passphrase for stored keys: ")
+        .with_display_mode(inquire::PasswordDisplayMode::Masked);
+    let prompt = match confirm {
+        true => prompt,
+        false => prompt.without_confirmation(),
+    };
+    Ok(prompt.prompt()?)
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0; 32];
+    let rounds = NonZeroU32::new(ITERATIONS).unwrap();
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        rounds,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &key).unwrap())
+}
+
+/// Encrypt `secret` with a key derived from `passphrase`, returning the salt,
+/// nonce and sealed secret encoded as base64.
+fn encrypt(secret: &str, passphrase: &str) -> Result<String> {
+    let random = SystemRandom::new();
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    random
+        .fill(&mut salt)
+        .and_then(|_| random.fill(&mut nonce))
+        .map_err(|_| anyhow!("no secure random numbers available"))?;
+
+    let mut sealed = secret.as_bytes().to_vec();
+    cipher(passphrase, &salt)
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut sealed,
+        )
+        .map_err(|_| anyhow!("could not encrypt the key"))?;
+    Ok(STANDARD.encode([&salt[..], &nonce, &sealed].concat()))
+}
+
+fn decrypt(encoded: &str, passphrase: &str) -> Result<String> {
+    let bytes = STANDARD.decode(encoded)?;
+    if bytes.len() < SALT_LEN + NONCE_LEN {
+        return Err(anyhow!("stored key is corrupted"));
+    }
+    let (salt, rest) = bytes.split_at(SALT_LEN);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).unwrap();
+    let mut sealed = sealed.to_vec();
+    let secret = cipher(passphrase, salt)
+        .open_in_place(nonce, Aad::empty(), &mut sealed)
+        .map_err(|_| anyhow!("wrong passphrase, or the stored key is corrupted"))?;
+    Ok(String::from_utf8(secret.to_vec())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let sealed = encrypt("sk-secret", "correct horse").unwrap();
+        assert!(!sealed.contains("sk-secret"));
+        assert_eq!(decrypt(&sealed, "correct horse").unwrap(), "sk-secret");
+        assert!(decrypt(&sealed, "battery staple").is_err());
+    }
+
+    #[test]
+    fn test_file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SecretStore::File;
+        store.set(dir.path(), "work", "sk-work", None).unwrap();
+        store.set(dir.path(), "home", "sk-home", None).unwrap();
+        assert_eq!(store.get(dir.path(), "work", None).unwrap(), "sk-work");
+
+        store.delete(dir.path(), "work").unwrap();
+        store.delete(dir.path(), "work").unwrap();
+        assert!(store.get(dir.path(), "work", None).is_err());
+        assert_eq!(store.get(dir.path(), "home", None).unwrap(), "sk-home");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = std::fs::metadata(dir.path().join(CREDENTIALS)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Local token counting, used to estimate context size before a request is
+//! sent rather than relying only on the `Usage` reported afterwards.
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::Message;
+
+/// Tokens added per message for role markup and separators, approximating
+/// the overhead chat models apply on top of the content itself.
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// Counts the tokens a model would see for a piece of text.
+pub trait Tokenizer: Send + Sync {
+    /// Number of tokens in `text`.
+    fn count(&self, text: &str) -> usize;
+
+    /// Number of tokens in a context of messages, including per-message overhead.
+    fn count_messages(&self, messages: &[Message]) -> usize {
+        messages
+            .iter()
+            .map(|m| self.count(&m.content) + MESSAGE_OVERHEAD)
+            .sum()
+    }
+}
+
+/// A cheap fallback assuming roughly four characters per token, which holds
+/// reasonably well for English text across most model vocabularies.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CharHeuristic;
+
+impl Tokenizer for CharHeuristic {
+    fn count(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(4)
+    }
+}
+
+/// Byte-pair encodings with publicly available tiktoken rank files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// Used by `gpt-4`, `gpt-3.5-turbo` and the `text-embedding-3` models
+    Cl100k,
+
+    /// Used by `gpt-4o` and later models
+    O200k,
+}
+
+impl Encoding {
+    /// Conventional file name of the encoding's rank file.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Encoding::Cl100k => "cl100k_base.tiktoken",
+            Encoding::O200k => "o200k_base.tiktoken",
+        }
+    }
+
+    /// Pre-tokenization pattern splitting text into pieces before merging.
+    /// tiktoken's trailing `\s+(?!\S)` alternative needs look-around, which
+    /// `regex` lacks, so it is emulated in `Bpe::pieces` instead.
+    fn pattern(&self) -> &'static str {
+        match self {
+            Encoding::Cl100k => concat!(
+                r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}",
+                r"| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+",
+            ),
+            Encoding::O200k => concat!(
+                r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+",
+                r"(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+                r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*",
+                r"(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+                r"|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+",
+            ),
+        }
+    }
+}
+
+/// A byte-pair encoding tokenizer using tiktoken-style merge ranks.
+pub struct Bpe {
+    ranks: HashMap<Vec<u8>, u32>,
+    pattern: Regex,
+}
+
+impl Bpe {
+    pub fn new(ranks: HashMap<Vec<u8>, u32>, encoding: Encoding) -> Self {
+        Self {
+            ranks,
+            pattern: Regex::new(encoding.pattern()).expect("invalid encoding pattern"),
+        }
+    }
+
+    /// Load a tiktoken rank file, where each line holds a base64-encoded
+    /// token followed by its rank, e.g. `IQ== 0`.
+    pub fn from_file(path: impl AsRef<Path>, encoding: Encoding) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+
+        let mut ranks = HashMap::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let (token, rank) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("malformed rank on line {}", number + 1))?;
+            ranks.insert(STANDARD.decode(token)?, rank.parse()?);
+        }
+        Ok(Self::new(ranks, encoding))
+    }
+
+    /// Encode `text` into token ranks.
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        let mut tokens = Vec::new();
+        for piece in self.pieces(text) {
+            match self.ranks.get(piece.as_bytes()) {
+                Some(&rank) => tokens.push(rank),
+                None => tokens.extend(self.merge(piece.as_bytes())),
+            }
+        }
+        tokens
+    }
+
+    /// Split text with the encoding's pattern. A whitespace run followed by
+    /// a non-whitespace character gives up its last character so it can
+    /// prefix the next piece, matching tiktoken's `\s+(?!\S)` behaviour.
+    fn pieces<'t>(&self, text: &'t str) -> Vec<&'t str> {
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while let Some(m) = self.pattern.find_at(text, start) {
+            let mut end = m.end();
+            let piece = m.as_str();
+            let followed_by_text = text[end..].starts_with(|c: char| !c.is_whitespace());
+            if followed_by_text
+                && piece.chars().count() > 1
+                && piece.chars().all(char::is_whitespace)
+                && !piece.ends_with(['\r', '\n'])
+            {
+                end -= piece.chars().last().map_or(0, char::len_utf8);
+            }
+            pieces.push(&text[m.start()..end]);
+            start = end;
+        }
+        pieces
+    }
+
+    /// Repeatedly merge the adjacent pair of parts with the lowest rank until
+    /// no mergeable pair remains, then map each part to its rank.
+    fn merge(&self, piece: &[u8]) -> Vec<u32> {
+        // part `i` spans bounds[i]..bounds[i + 1], starting from single bytes
+        let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+        while let Some((_, i)) = bounds
+            .windows(3)
+            .enumerate()
+            .filter_map(|(i, w)| self.ranks.get(&piece[w[0]..w[2]]).map(|&rank| (rank, i)))
+            .min()
+        {
+            bounds.remove(i + 1);
+        }
+
+        // every single byte has a rank in a complete vocabulary
+        bounds
+            .windows(2)
+            .filter_map(|w| self.ranks.get(&piece[w[0]..w[1]]).copied())
+            .collect()
+    }
+}
+
+impl Tokenizer for Bpe {
+    fn count(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+}
+
+/// Resolve the tokenizer for `encoding`, loading its rank file from `dir`.
+/// Without an encoding or a directory, tokens are estimated by
+/// `CharHeuristic`; a rank file missing from the given directory is an error.
+pub fn resolve(encoding: Option<Encoding>, dir: Option<&Path>) -> Result<Box<dyn Tokenizer>> {
+    match (encoding, dir) {
+        (Some(encoding), Some(dir)) => {
+            let bpe = Bpe::from_file(dir.join(encoding.file_name()), encoding)?;
+            Ok(Box::new(bpe))
+        }
+        _ => Ok(Box::new(CharHeuristic)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Every single byte plus a handful of merges, ranked like a real vocabulary.
+    fn toy() -> Bpe {
+        let mut ranks: HashMap<Vec<u8>, u32> = (0..=255u8).map(|b| (vec![b], b as u32)).collect();
+        for (i, token) in ["he", "ll", "hell", "hello", " w", " wor", " world"]
+            .iter()
+            .enumerate()
+        {
+            ranks.insert(token.as_bytes().to_vec(), 256 + i as u32);
+        }
+        Bpe::new(ranks, Encoding::Cl100k)
+    }
+
+    #[test]
+    fn test_char_heuristic() {
+        assert_eq!(CharHeuristic.count(""), 0);
+        assert_eq!(CharHeuristic.count("abcd"), 1);
+        assert_eq!(CharHeuristic.count("abcde"), 2);
+        let messages = [Message::user("abcd"), Message::assistant("")];
+        assert_eq!(
+            CharHeuristic.count_messages(&messages),
+            1 + 2 * MESSAGE_OVERHEAD
+        );
+    }
+
+    #[test]
+    fn test_bpe_pieces() {
+        let bpe = toy();
+        assert_eq!(bpe.pieces("hello world"), vec!["hello", " world"]);
+        assert_eq!(bpe.pieces("hello   world"), vec!["hello", "  ", " world"]);
+        assert_eq!(
+            bpe.pieces("it's 12345!\n\n"),
+            vec!["it", "'s", " ", "123", "45", "!\n\n"]
+        );
+        assert_eq!(bpe.pieces("trailing  "), vec!["trailing", "  "]);
+    }
+
+    #[test]
+    fn test_bpe_encode() {
+        let bpe = toy();
+        assert_eq!(bpe.encode("hello world"), vec![259, 262]);
+        // "helo" merges to "he" + "l" + "o"
+        assert_eq!(bpe.encode("helo"), vec![256, b'l' as u32, b'o' as u32]);
+        // " hello" merges up to " " + "hello"
+        assert_eq!(bpe.count("hello hello"), 3);
+    }
+
+    #[test]
+    fn test_bpe_from_file() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("air-test-{}.tiktoken", std::process::id()));
+        let mut file = File::create(&path)?;
+        for b in 0..=255u8 {
+            writeln!(file, "{} {}", STANDARD.encode([b]), b)?;
+        }
+        writeln!(file, "{} 256", STANDARD.encode("ab"))?;
+        drop(file);
+
+        let bpe = Bpe::from_file(&path, Encoding::Cl100k)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(bpe.encode("abc"), vec![256, b'c' as u32]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_falls_back() {
+        let tokenizer = resolve(Some(Encoding::O200k), None).unwrap();
+        assert_eq!(tokenizer.count("abcdefgh"), 2);
+        let dir = std::env::temp_dir().join("air-missing-tokenizers");
+        assert_eq!(resolve(None, Some(&dir)).unwrap().count("abcdefgh"), 2);
+        assert!(resolve(Some(Encoding::O200k), Some(&dir)).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+
+/// A function the model may call, described by a JSON schema of its
+/// arguments. Serializes to OpenAI's `{"type": "function", ...}` shape.
+///
+/// # Examples
+///
+/// ```
+/// use air::tool::Tool;
+/// use serde_json::json;
+///
+/// let tool = Tool::new("get_weather", "Current weather for a city").with_parameters(json!({
+///     "type": "object",
+///     "properties": {"city": {"type": "string"}},
+///     "required": ["city"],
+/// }));
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(into = "WireTool", from = "WireTool")]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+
+    /// JSON schema of the arguments object
+    pub parameters: serde_json::Value,
+}
+
+impl Tool {
+    pub fn new<S: Into<String>>(name: S, description: S) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameters: serde_json::json!({"type": "object", "properties": {}}),
+        }
+    }
+
+    pub fn with_parameters(mut self, parameters: serde_json::Value) -> Self {
+        self.parameters = parameters;
+        self
+    }
+}
+
+/// A request from the model to call one of the offered tools.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "WireToolCall", from = "WireToolCall")]
+pub struct ToolCall {
+    /// Identifier to quote in the `Role::Tool` message carrying the result
+    pub id: String,
+    pub name: String,
+
+    /// Arguments as the JSON text generated by the model, which is not
+    /// guaranteed to be valid
+    pub arguments: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct WireTool {
+    #[serde(rename = "type")]
+    kind: String,
+    function: WireFunction,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct WireFunction {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    parameters: serde_json::Value,
+}
+
+impl From<Tool> for WireTool {
+    fn from(tool: Tool) -> Self {
+        Self {
+            kind: "function".to_string(),
+            function: WireFunction {
+                name: tool.name,
+                description: tool.description,
+                parameters: tool.parameters,
+            },
+        }
+    }
+}
+
+impl From<WireTool> for Tool {
+    fn from(wire: WireTool) -> Self {
+        Self {
+            name: wire.function.name,
+            description: wire.function.description,
+            parameters: wire.function.parameters,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct WireToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: WireArguments,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct WireArguments {
+    name: String,
+    #[serde(default)]
+    arguments: String,
+}
+
+impl From<ToolCall> for WireToolCall {
+    fn from(call: ToolCall) -> Self {
+        Self {
+            id: call.id,
+            kind: "function".to_string(),
+            function: WireArguments {
+                name: call.name,
+                arguments: call.arguments,
+            },
+        }
+    }
+}
+
+impl From<WireToolCall> for ToolCall {
+    fn from(wire: WireToolCall) -> Self {
+        Self {
+            id: wire.id,
+            name: wire.function.name,
+            arguments: wire.function.arguments,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tool_serialize() {
+        let tool = Tool::new("get_weather", "Current weather for a city");
+        assert_eq!(
+            serde_json::to_value(&tool).unwrap(),
+            json!({
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "description": "Current weather for a city",
+                    "parameters": {"type": "object", "properties": {}}
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_tool_call_round_trip() {
+        let wire = json!({
+            "id": "call_1",
+            "type": "function",
+            "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+        });
+        let call: ToolCall = serde_json::from_value(wire.clone()).unwrap();
+        assert_eq!(call.name, "get_weather");
+        assert_eq!(call.arguments, r#"{"city":"Paris"}"#);
+        assert_eq!(serde_json::to_value(&call).unwrap(), wire);
+    }
+}
@@ -1,12 +1,194 @@
-use crate::{Message, Role};
-use anyhow::Result;
+use crate::{host::Usage, tool::ToolCall, tree::ConversationTree, Message, Role};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
 use enum_iterator::all;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::{
-    io::{BufRead, Read, Write},
+    fmt::Display,
+    io::{Read, Write},
+    path::Path,
     str::FromStr,
+    sync::OnceLock,
+    time::Duration,
 };
 
+/// Details of how a recorded message came about. Every field is optional, and
+/// none of it is part of the context rebuilt from a transcript by `load`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// When the message was sent or its response completed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+
+    /// The responding provider and model, as the provider displays itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+
+    /// Time taken for the response to complete, including any retries
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "latency_ms",
+        with = "millis"
+    )]
+    pub latency: Option<Duration>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+}
+
+impl Metadata {
+    /// Metadata holding only the current time.
+    pub fn now() -> Self {
+        Self {
+            timestamp: Some(Utc::now()),
+            ..Default::default()
+        }
+    }
+}
+
+/// (De)serialize an optional duration as whole milliseconds.
+mod millis {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(value: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        value.map(|d| d.as_millis() as u64).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(d)?.map(Duration::from_millis))
+    }
+}
+
+/// A recorded message along with any metadata recorded for it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(flatten)]
+    pub message: Message,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+}
+
+/// Borrowed form of `Entry` for recording.
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    #[serde(flatten)]
+    message: &'a Message,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<&'a Metadata>,
+}
+
+/// The JSON after the role in a plain-text header: the entry's metadata, and
+/// the message's tool fields, which have no other place in that format.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+
+    #[serde(flatten)]
+    metadata: Metadata,
+}
+
+impl Header {
+    /// The header JSON for `entry`, or `None` if the role alone describes it.
+    fn of(entry: &EntryRef) -> Option<Self> {
+        let message = entry.message;
+        let tools = !message.tool_calls.is_empty() || message.tool_call_id.is_some();
+        (tools || entry.metadata.is_some()).then(|| Self {
+            tool_calls: message.tool_calls.clone(),
+            tool_call_id: message.tool_call_id.clone(),
+            metadata: entry.metadata.cloned().unwrap_or_default(),
+        })
+    }
+
+    /// An empty entry for a message with `role` described by this header.
+    /// Metadata left empty beside tool fields was never recorded.
+    fn entry(self, role: Role) -> Entry {
+        let tools = !self.tool_calls.is_empty() || self.tool_call_id.is_some();
+        let recorded = !tools || self.metadata != Metadata::default();
+        Entry {
+            message: Message {
+                tool_calls: self.tool_calls,
+                tool_call_id: self.tool_call_id,
+                ..Message::new(role, "")
+            },
+            metadata: recorded.then_some(self.metadata),
+        }
+    }
+}
+
+/// On-disk layout of a transcript.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TranscriptFormat {
+    /// `ROLE:` headers, each followed by the message content
+    #[default]
+    Text,
+
+    /// A JSON array of messages
+    Json,
+
+    /// One JSON message per line, which stays valid as it is appended to
+    JsonLines,
+}
+
+impl TranscriptFormat {
+    /// The format conventionally implied by a file's extension, if any.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(TranscriptFormat::Json),
+            "jsonl" | "ndjson" => Some(TranscriptFormat::JsonLines),
+            "txt" => Some(TranscriptFormat::Text),
+            _ => None,
+        }
+    }
+
+    /// Guess the format of a transcript's contents. Anything that does not
+    /// parse as JSON messages is taken to be plain text.
+    pub fn detect(contents: &str) -> Self {
+        let trimmed = contents.trim_start();
+        if trimmed.starts_with('[') && parse_json(contents).is_ok() {
+            TranscriptFormat::Json
+        } else if trimmed.starts_with('{') && parse_json_lines(contents).is_ok() {
+            TranscriptFormat::JsonLines
+        } else {
+            TranscriptFormat::Text
+        }
+    }
+}
+
+impl FromStr for TranscriptFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "txt" => Ok(TranscriptFormat::Text),
+            "json" => Ok(TranscriptFormat::Json),
+            "jsonl" | "ndjson" => Ok(TranscriptFormat::JsonLines),
+            _ => Err(format!("unknown transcript format: {s}")),
+        }
+    }
+}
+
+impl Display for TranscriptFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TranscriptFormat::Text => write!(f, "text"),
+            TranscriptFormat::Json => write!(f, "json"),
+            TranscriptFormat::JsonLines => write!(f, "jsonl"),
+        }
+    }
+}
+
 fn role_regex() -> String {
     all::<Role>()
         .map(|r| r.to_string().to_uppercase() + ":")
@@ -15,76 +197,271 @@ fn role_regex() -> String {
 }
 
 /// Loads a transcript from a Read source (e.g. File, socket, etc.), returning
-/// the contents as an ordered vector of `Message`s.
+/// the contents as an ordered vector of `Message`s. The format is detected
+/// from the contents; see `TranscriptFormat::detect`. Recorded metadata is
+/// discarded, leaving a context suitable for `Client::with_context`.
 pub fn load(source: impl Read) -> Result<Vec<Message>> {
-    let mut reader = std::io::BufReader::new(source);
-    let mut messages = Vec::<Message>::new();
-
-    // Initialize role or return early
-    let mut role: Role;
-    let mut buffer = String::with_capacity(1024);
-    match reader.read_line(&mut buffer)? {
-        0 => return Ok(messages), // Empty file
-        _ => match Role::from_str(buffer.trim_end_matches(&[':', '\n'])) {
-            Ok(r) => role = r, // First role found
-            Err(_) => {
-                // Not a transcript file: return the entire thing as user context
-                reader.read_to_string(&mut buffer).expect("Unable to read input context");
-                let message = Message::new(Role::User, buffer);
-                messages.push(message);
-                return Ok(messages);
-            }
+    Ok(messages(load_entries(source)?))
+}
+
+/// Loads a transcript known to be in the given format.
+pub fn load_as(mut source: impl Read, format: TranscriptFormat) -> Result<Vec<Message>> {
+    let mut contents = String::new();
+    source.read_to_string(&mut contents)?;
+    Ok(messages(parse(&contents, format)?))
+}
+
+/// Loads a transcript like `load`, keeping the metadata recorded with each
+/// message.
+pub fn load_entries(mut source: impl Read) -> Result<Vec<Entry>> {
+    let mut contents = String::new();
+    source.read_to_string(&mut contents)?;
+    parse(&contents, TranscriptFormat::detect(&contents))
+}
+
+/// Loads a conversation tree written by `save_tree`. A linear transcript in
+/// any format is also accepted, becoming a tree with a single branch.
+pub fn load_tree(mut source: impl Read) -> Result<ConversationTree> {
+    let mut contents = String::new();
+    source.read_to_string(&mut contents)?;
+    if contents.trim_start().starts_with('{') {
+        if let Ok(tree) = serde_json::from_str(&contents) {
+            return Ok(tree);
         }
     }
-    buffer.clear();
+    let mut tree = ConversationTree::new();
+    for entry in parse(&contents, TranscriptFormat::detect(&contents))? {
+        tree.push_entry(entry);
+    }
+    Ok(tree)
+}
+
+/// Writes every branch of a conversation tree as a single JSON document,
+/// with each message recording the index of the one it follows.
+pub fn save_tree(tree: &ConversationTree, mut sink: impl Write) -> Result<()> {
+    serde_json::to_writer_pretty(&mut sink, tree)?;
+    writeln!(sink)?;
+    sink.flush()?;
+    Ok(())
+}
+
+fn messages(entries: Vec<Entry>) -> Vec<Message> {
+    entries.into_iter().map(|entry| entry.message).collect()
+}
+
+fn parse(contents: &str, format: TranscriptFormat) -> Result<Vec<Entry>> {
+    match format {
+        TranscriptFormat::Text => parse_text(contents),
+        TranscriptFormat::Json => parse_json(contents),
+        TranscriptFormat::JsonLines => parse_json_lines(contents),
+    }
+}
 
-    let re = Regex::new(&role_regex())?;
-    while let Ok(n) = reader.read_line(&mut buffer) {
-        if n == 0 {
-            // EOF; save final message
-            let message = Message::new(role, buffer.trim_end());
-            messages.push(message);
-            break;
-        } else if let Some(r) = re.find_at(&buffer, buffer.len() - n) {
-            // Found new role: save current message up to role and create new one
-            let content = buffer[..buffer.len() - n].trim_end();
-            let message = Message::new(role, content);
-            messages.push(message);
+/// Parse a JSON array of messages, accepting one left unterminated by a
+/// session that ended before its transcript was closed.
+fn parse_json(contents: &str) -> Result<Vec<Entry>> {
+    let contents = contents.trim();
+    match contents.ends_with(']') {
+        true => Ok(serde_json::from_str(contents)?),
+        false => Ok(serde_json::from_str(&format!("{contents}]"))?),
+    }
+}
 
-            // Prepare next message's role and its buffer
-            role = Role::from_str(r.as_str().trim_end_matches(&[':', '\n'])).unwrap();
-            buffer.clear();
+fn parse_json_lines(contents: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => bail!("line {}: {err}", i + 1),
         }
-        // Otherwise, keep appending to buffer
     }
+    Ok(entries)
+}
 
-    Ok(messages)
+/// Parse the plain-text format: each message is a `ROLE:` header line,
+/// optionally followed by its metadata and tool fields as JSON, then its
+/// escaped content and a blank line. A source not starting with a header is taken whole as a
+/// single user message.
+fn parse_text(contents: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::<Entry>::new();
+    let mut current: Option<(Entry, String)> = None;
+    for line in contents.split_inclusive('\n') {
+        if let Some(entry) = parse_header(line.trim_end_matches('\n')) {
+            entries.extend(current.take().map(finish_entry));
+            current = Some((entry, String::new()));
+            continue;
+        }
+        match current {
+            Some((_, ref mut buffer)) => buffer.push_str(unescape(line)),
+            None => {
+                // Not a transcript file: return the entire thing as user context
+                let message = Message::new(Role::User, contents);
+                return Ok(vec![Entry {
+                    message,
+                    metadata: None,
+                }]);
+            }
+        }
+    }
+    entries.extend(current.map(finish_entry));
+    Ok(entries)
+}
+
+/// An empty entry for a header line, or `None` if the line is not a header.
+/// A header whose JSON does not parse is taken to be content.
+fn parse_header(line: &str) -> Option<Entry> {
+    let captures = header().captures(line)?;
+    let role = Role::from_str(captures[1].trim_end_matches(':')).ok()?;
+    match captures.get(2) {
+        None => Some(Entry {
+            message: Message::new(role, ""),
+            metadata: None,
+        }),
+        Some(json) => Some(
+            serde_json::from_str::<Header>(json.as_str())
+                .ok()?
+                .entry(role),
+        ),
+    }
+}
+
+/// Complete an entry with its raw content from a plain-text transcript,
+/// dropping the blank line `record` writes after the content.
+fn finish_entry((mut entry, buffer): (Entry, String)) -> Entry {
+    let content = buffer
+        .strip_suffix("\n\n")
+        .or_else(|| buffer.strip_suffix('\n'))
+        .unwrap_or(&buffer);
+    entry.message.content = content.to_string();
+    entry
+}
+
+/// A line that separates messages, e.g. `USER:` or `ASSISTANT: {"usage": ...}`.
+fn header() -> &'static Regex {
+    static HEADER: OnceLock<Regex> = OnceLock::new();
+    HEADER.get_or_init(|| Regex::new(&format!(r"^({})(?: (\{{.*\}}))?\r?$", role_regex())).unwrap())
+}
+
+/// A content line that could be mistaken for a header, or for an escaped
+/// one: any number of backslashes, then a role and colon.
+fn escapable() -> &'static Regex {
+    static ESCAPABLE: OnceLock<Regex> = OnceLock::new();
+    ESCAPABLE.get_or_init(|| Regex::new(&format!(r"^\\*(?:{})", role_regex())).unwrap())
+}
+
+/// Prefix a backslash to every escapable line, so that no line of the
+/// content can be read back as a header.
+fn escape(content: &str) -> String {
+    content
+        .split('\n')
+        .map(|line| match escapable().is_match(line) {
+            true => format!("\\{line}"),
+            false => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reverse `escape` for a single line.
+fn unescape(line: &str) -> &str {
+    match line.starts_with('\\') && escapable().is_match(line) {
+        true => &line[1..],
+        false => line,
+    }
 }
 
 pub struct Transcript<'a, T: Write> {
     sink: Option<&'a mut T>,
+    format: TranscriptFormat,
+    recorded: usize,
 }
 
 impl<'a, T: Write> Transcript<'a, T> {
     pub fn new(sink: &'a mut T) -> Self {
-        Self { sink: Some(sink) }
+        Self::conditionally(Some(sink))
     }
 
     /// Create a new `Transcript` with an optional sink. If `None`, the sink
     /// is never written to.
     pub fn conditionally(sink: Option<&'a mut T>) -> Self {
-        Self { sink }
+        Self {
+            sink,
+            format: TranscriptFormat::default(),
+            recorded: 0,
+        }
+    }
+
+    /// Record in the given format rather than plain text.
+    pub fn with_format(mut self, format: TranscriptFormat) -> Self {
+        self.format = format;
+        self
     }
 
     /// Record a message to the transcript if a sink was provided on `Transcript`
     /// creation.
     pub fn record(&mut self, message: &Message) -> Result<()> {
-        if let Some(&mut ref mut s) = self.sink { 
-            writeln!(s, "{}:", message.role.to_string().to_uppercase())?;
-            for line in message.content.lines() {
-                writeln!(s, "{line}")?;
+        self.write(EntryRef {
+            message,
+            metadata: None,
+        })
+    }
+
+    /// Record a message along with metadata describing it.
+    pub fn record_with(&mut self, message: &Message, metadata: &Metadata) -> Result<()> {
+        self.write(EntryRef {
+            message,
+            metadata: Some(metadata),
+        })
+    }
+
+    /// Record an entry, with its metadata if it has any.
+    pub fn record_entry(&mut self, entry: &Entry) -> Result<()> {
+        self.write(EntryRef {
+            message: &entry.message,
+            metadata: entry.metadata.as_ref(),
+        })
+    }
+
+    fn write(&mut self, entry: EntryRef) -> Result<()> {
+        if let Some(&mut ref mut s) = self.sink {
+            match self.format {
+                TranscriptFormat::Text => {
+                    write!(s, "{}:", entry.message.role.to_string().to_uppercase())?;
+                    match Header::of(&entry) {
+                        Some(header) => writeln!(s, " {}", serde_json::to_string(&header)?)?,
+                        None => writeln!(s)?,
+                    }
+                    write!(s, "{}\n\n", escape(&entry.message.content))?;
+                }
+                TranscriptFormat::Json => {
+                    let separator = if self.recorded == 0 { "[" } else { "," };
+                    writeln!(s, "{separator}")?;
+                    write!(s, "{}", serde_json::to_string(&entry)?)?;
+                    // the closing newline waits for the next separator
+                    s.flush()?;
+                }
+                TranscriptFormat::JsonLines => {
+                    writeln!(s, "{}", serde_json::to_string(&entry)?)?;
+                }
             }
-            writeln!(s)?;       
+            self.recorded += 1;
+        }
+        Ok(())
+    }
+
+    /// Write anything needed to complete the transcript, i.e. the closing
+    /// bracket of a JSON array, after which nothing more is recorded. `load`
+    /// still accepts a JSON transcript that was never finished.
+    pub fn finish(&mut self) -> Result<()> {
+        if let (Some(s), TranscriptFormat::Json) = (self.sink.take(), self.format) {
+            match self.recorded {
+                0 => writeln!(s, "[]")?,
+                _ => writeln!(s, "\n]")?,
+            }
+            s.flush()?;
         }
         Ok(())
     }
@@ -95,7 +472,10 @@ mod tests {
     use super::*;
     use crate::Role;
     use enum_iterator::all;
-    use std::{collections::HashSet, io::{Cursor, Seek}};
+    use std::{
+        collections::HashSet,
+        io::{Cursor, Seek},
+    };
 
     /// Regex should match ROLE_0:|ROLE_1:|...ROLE_N: for an arbitrary ordering
     /// and definition of `Role`s.
@@ -114,10 +494,7 @@ mod tests {
         let mut sink = Cursor::new(buffer);
         let mut transcript = Transcript::conditionally(Some(&mut sink));
 
-        let message = Message {
-            role: Role::User,
-            content: "Hello, world!".to_string(),
-        };
+        let message = Message::new(Role::User, "Hello, world!");
         transcript.record(&message)?;
 
         sink.rewind()?;
@@ -136,18 +513,9 @@ mod tests {
         let mut transcript = Transcript::conditionally(Some(&mut sink));
 
         let messages = vec![
-            Message {
-                role: Role::User,
-                content: "Hello, assistant!".to_string(),
-            },
-            Message {
-                role: Role::Assistant,
-                content: "Hello, user!".to_string(),
-            },
-            Message {
-                role: Role::Assistant,
-                content: "Hello again, user!".to_string(),
-            },
+            Message::new(Role::User, "Hello, assistant!"),
+            Message::new(Role::Assistant, "Hello, user!"),
+            Message::new(Role::Assistant, "Hello again, user!"),
         ];
         for message in messages {
             transcript.record(&message)?;
@@ -157,7 +525,7 @@ mod tests {
         let mut contents = String::new();
         sink.read_to_string(&mut contents)?;
         assert_eq!(
-            contents, 
+            contents,
             "USER:\nHello, assistant!\n\nASSISTANT:\nHello, user!\n\nASSISTANT:\nHello again, user!\n\n"
         );
         Ok(())
@@ -170,18 +538,9 @@ mod tests {
         let mut transcript = Transcript::new(&mut sink);
 
         let messages = vec![
-            Message {
-                role: Role::User,
-                content: "Hello, assistant!".to_string(),
-            },
-            Message {
-                role: Role::Assistant,
-                content: "Hello, user!".to_string(),
-            },
-            Message {
-                role: Role::Assistant,
-                content: "Hello again, user!".to_string(),
-            },
+            Message::new(Role::User, "Hello, assistant!"),
+            Message::new(Role::Assistant, "Hello, user!"),
+            Message::new(Role::Assistant, "Hello again, user!"),
         ];
         for message in &messages {
             transcript.record(message)?;
@@ -201,14 +560,11 @@ mod tests {
     fn test_load_bad_format() -> Result<()> {
         let buffer = Vec::<u8>::new();
         let mut sink = std::io::Cursor::new(buffer);
-    
+
         let content = r#"<Bad format>ASSISTANT:a\23""@#$!@#:\n\\1\r"#;
         write!(sink, "{content}")?;
-        
-        let message = Message {
-            role: Role::User,
-            content: content.to_string(),
-        };
+
+        let message = Message::new(Role::User, content);
 
         sink.rewind()?;
         let loaded = load(sink)?;
@@ -216,4 +572,228 @@ mod tests {
         assert_eq!(loaded[0], message);
         Ok(())
     }
+
+    fn recorded(format: TranscriptFormat, messages: &[Message]) -> Result<String> {
+        let mut sink = Cursor::new(Vec::<u8>::new());
+        let mut transcript = Transcript::new(&mut sink).with_format(format);
+        for message in messages {
+            transcript.record(message)?;
+        }
+        transcript.finish()?;
+        Ok(String::from_utf8(sink.into_inner())?)
+    }
+
+    #[test]
+    fn test_transcript_json_formats() -> Result<()> {
+        let messages = vec![
+            Message::system("Be brief."),
+            Message::user("USER:\nnot a header"),
+            Message::assistant("Hello, user!\n\n"),
+        ];
+
+        let json = recorded(TranscriptFormat::Json, &messages)?;
+        assert!(json.starts_with("[\n{"));
+        assert!(json.ends_with("}\n]\n"));
+        assert_eq!(TranscriptFormat::detect(&json), TranscriptFormat::Json);
+        assert_eq!(load(json.as_bytes())?, messages);
+
+        let lines = recorded(TranscriptFormat::JsonLines, &messages)?;
+        assert_eq!(lines.lines().count(), 3);
+        assert_eq!(
+            TranscriptFormat::detect(&lines),
+            TranscriptFormat::JsonLines
+        );
+        assert_eq!(load(lines.as_bytes())?, messages);
+
+        assert_eq!(recorded(TranscriptFormat::Json, &[])?, "[]\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_unfinished_json() -> Result<()> {
+        let contents = "[\n{\"role\":\"user\",\"content\":\"Hi\"}";
+        assert_eq!(load(contents.as_bytes())?, vec![Message::user("Hi")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_falls_back_to_text() -> Result<()> {
+        let contents = "{\"this\": \"is a question about JSON\"}";
+        assert_eq!(TranscriptFormat::detect(contents), TranscriptFormat::Text);
+        assert_eq!(load(contents.as_bytes())?, vec![Message::user(contents)]);
+        assert!(load_as(contents.as_bytes(), TranscriptFormat::JsonLines).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_round_trip() -> Result<()> {
+        let mut tree = ConversationTree::new();
+        tree.push(Message::user("Name a colour"));
+        let first = tree.push(Message::assistant("Red"));
+        tree.regenerate(Message::assistant("Blue"));
+        tree.switch(first);
+
+        let mut sink = Vec::new();
+        save_tree(&tree, &mut sink)?;
+        assert_eq!(load_tree(sink.as_slice())?, tree);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_tree_from_linear() -> Result<()> {
+        let contents = "USER:\nHi\n\nASSISTANT:\nHello\n\n";
+        let tree = load_tree(contents.as_bytes())?;
+        assert_eq!(tree.messages(), load(contents.as_bytes())?);
+        assert_eq!(tree.len(), 2);
+
+        let contents = "{\"role\":\"user\",\"content\":\"Hi\"}\n";
+        assert_eq!(
+            load_tree(contents.as_bytes())?.messages(),
+            vec![Message::user("Hi")]
+        );
+        Ok(())
+    }
+
+    mod roundtrip {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn role() -> impl Strategy<Value = Role> {
+            proptest::sample::select(all::<Role>().collect::<Vec<_>>())
+        }
+
+        fn tool_call() -> impl Strategy<Value = ToolCall> {
+            ("[a-z0-9_]{1,8}", "[a-z_]{1,8}", ".*").prop_map(|(id, name, arguments)| ToolCall {
+                id,
+                name,
+                arguments,
+            })
+        }
+
+        fn message() -> impl Strategy<Value = Message> {
+            let tool_calls = prop::collection::vec(tool_call(), 0..3);
+            let tool_call_id = proptest::option::of("[a-z0-9_]{1,8}");
+            (role(), content(), tool_calls, tool_call_id).prop_map(
+                |(role, content, tool_calls, tool_call_id)| Message {
+                    tool_calls,
+                    tool_call_id,
+                    ..Message::new(role, content)
+                },
+            )
+        }
+
+        /// Content biased towards lines that resemble headers and escapes.
+        fn content() -> impl Strategy<Value = String> {
+            let line = prop_oneof![
+                ".*",
+                Just("USER:".to_string()),
+                Just("ASSISTANT: quoted".to_string()),
+                Just("\\SYSTEM:".to_string()),
+                Just("\\\\TOOL:\r".to_string()),
+                Just("   ".to_string()),
+                Just(String::new()),
+            ];
+            prop::collection::vec(line, 0..6).prop_map(|lines| lines.join("\n"))
+        }
+
+        proptest! {
+            #[test]
+            fn test_round_trip(messages in prop::collection::vec(message(), 0..5)) {
+                let formats = [
+                    TranscriptFormat::Text,
+                    TranscriptFormat::Json,
+                    TranscriptFormat::JsonLines,
+                ];
+                for format in formats {
+                    let contents = recorded(format, &messages).unwrap();
+                    prop_assert_eq!(&load(contents.as_bytes()).unwrap(), &messages);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_quoted_header() -> Result<()> {
+        let messages = vec![
+            Message::user("What does a transcript look like?"),
+            Message::assistant("Like this:\nUSER:\nHi\n\\ASSISTANT:\n\n"),
+        ];
+        let text = recorded(TranscriptFormat::Text, &messages)?;
+        assert!(text.contains("\n\\USER:\n"));
+        assert!(text.contains("\n\\\\ASSISTANT:\n"));
+        assert_eq!(load(text.as_bytes())?, messages);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_round_trip() -> Result<()> {
+        let metadata = Metadata {
+            timestamp: Some("2024-04-01T12:00:00Z".parse()?),
+            provider: Some("OpenAI gpt-4".to_string()),
+            usage: Some(serde_json::from_str(r#"{"total_tokens": 13}"#)?),
+            latency: Some(Duration::from_millis(1250)),
+            finish_reason: Some("stop".to_string()),
+        };
+        let question = Message::user("Hello, assistant!");
+        let answer = Message::assistant("Hello, user!");
+
+        for format in [TranscriptFormat::Text, TranscriptFormat::JsonLines] {
+            let mut sink = Cursor::new(Vec::<u8>::new());
+            let mut transcript = Transcript::new(&mut sink).with_format(format);
+            transcript.record(&question)?;
+            transcript.record_with(&answer, &metadata)?;
+            let contents = String::from_utf8(sink.into_inner())?;
+            assert!(contents.contains(r#""latency_ms":1250"#));
+
+            let entries = load_entries(contents.as_bytes())?;
+            assert_eq!(entries[0].metadata, None);
+            assert_eq!(entries[1].metadata.as_ref(), Some(&metadata));
+            assert_eq!(
+                load(contents.as_bytes())?,
+                vec![question.clone(), answer.clone()]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_tool_fields() -> Result<()> {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "add".to_string(),
+            arguments: r#"{"a": 2}"#.to_string(),
+        };
+        let request = Message {
+            tool_calls: vec![call],
+            ..Message::assistant("")
+        };
+        let result = Message::tool("call_1", "2");
+
+        let mut sink = Cursor::new(Vec::<u8>::new());
+        let mut transcript = Transcript::new(&mut sink);
+        transcript.record(&request)?;
+        transcript.record_with(&result, &Metadata::now())?;
+        let contents = String::from_utf8(sink.into_inner())?;
+        assert!(contents.starts_with(r#"ASSISTANT: {"tool_calls":[{"id":"call_1""#));
+
+        let entries = load_entries(contents.as_bytes())?;
+        assert_eq!(entries[0].message, request);
+        assert_eq!(entries[0].metadata, None);
+        assert_eq!(entries[1].message, result);
+        assert!(entries[1].metadata.as_ref().unwrap().timestamp.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_malformed_metadata_as_content() -> Result<()> {
+        let contents = "USER:\nHi\n\nASSISTANT:\nHello\nUSER: {not json}\n\n";
+        assert_eq!(
+            load(contents.as_bytes())?,
+            vec![
+                Message::user("Hi"),
+                Message::assistant("Hello\nUSER: {not json}")
+            ]
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,373 @@
+use serde::{Deserialize, Serialize};
+
+use crate::transcript::{Entry, Metadata};
+use crate::{Message, Role};
+
+/// Identifies a message within a `ConversationTree`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NodeId(usize);
+
+/// A message in a `ConversationTree` and its place among the others.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub message: Message,
+    pub metadata: Option<Metadata>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl Node {
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    /// Replies to this message, oldest first.
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+/// A conversation kept as a tree of messages, so that editing an earlier
+/// prompt or regenerating a reply starts a new branch instead of discarding
+/// history. One branch is active at a time; its messages, from the root to
+/// the active node, form the context sent to a model.
+///
+/// # Examples
+///
+/// ```
+/// use air::tree::ConversationTree;
+/// use air::Message;
+///
+/// let mut tree = ConversationTree::new();
+/// let question = tree.push(Message::user("Name a colour"));
+/// let first = tree.push(Message::assistant("Red"));
+/// let second = tree.regenerate(Message::assistant("Blue")).unwrap();
+/// assert_eq!(tree.siblings(second), vec![first, second]);
+///
+/// tree.switch(first);
+/// assert_eq!(tree.messages(), vec![Message::user("Name a colour"), Message::assistant("Red")]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "TreeFile", try_from = "TreeFile")]
+pub struct ConversationTree {
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+    active: Option<NodeId>,
+}
+
+impl ConversationTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A tree holding `messages` as its single branch.
+    pub fn from_messages<I: IntoIterator<Item = Message>>(messages: I) -> Self {
+        let mut tree = Self::new();
+        for message in messages {
+            tree.push(message);
+        }
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id.0)
+    }
+
+    /// The last message of the active branch, if any.
+    pub fn active(&self) -> Option<NodeId> {
+        self.active
+    }
+
+    /// Append a message to the active branch, making it the active node.
+    pub fn push(&mut self, message: Message) -> NodeId {
+        self.attach(self.active, message, None)
+    }
+
+    /// Append a message with any metadata recorded for it, as `push`.
+    pub fn push_entry(&mut self, entry: Entry) -> NodeId {
+        self.attach(self.active, entry.message, entry.metadata)
+    }
+
+    /// The nodes of the active branch, from the root to the active node.
+    pub fn path(&self) -> Vec<NodeId> {
+        let mut path = Vec::new();
+        let mut current = self.active;
+        while let Some(id) = current {
+            path.push(id);
+            current = self.nodes[id.0].parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// The messages of the active branch, i.e. the context to send.
+    pub fn messages(&self) -> Vec<Message> {
+        self.path()
+            .into_iter()
+            .map(|id| self.nodes[id.0].message.clone())
+            .collect()
+    }
+
+    /// The messages of the active branch with their metadata, e.g. to record
+    /// them in a transcript.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.path()
+            .into_iter()
+            .map(|id| Entry {
+                message: self.nodes[id.0].message.clone(),
+                metadata: self.nodes[id.0].metadata.clone(),
+            })
+            .collect()
+    }
+
+    /// Alternatives to `id`, including itself, oldest first.
+    pub fn siblings(&self, id: NodeId) -> Vec<NodeId> {
+        match self.get(id).and_then(Node::parent) {
+            Some(parent) => self.nodes[parent.0].children.clone(),
+            None => self.roots.clone(),
+        }
+    }
+
+    /// Make `id` the active node, so that the next message pushed starts a
+    /// new branch from it. Returns false if there is no such node.
+    pub fn fork(&mut self, id: NodeId) -> bool {
+        if self.get(id).is_none() {
+            return false;
+        }
+        self.active = Some(id);
+        true
+    }
+
+    /// Deactivate the tree, so that the next message pushed starts a new
+    /// conversation alongside the others.
+    pub fn reset(&mut self) {
+        self.active = None;
+    }
+
+    /// Activate the branch through `id`, following the most recent reply at
+    /// each step down to its last message. Returns false if there is no such
+    /// node.
+    pub fn switch(&mut self, id: NodeId) -> bool {
+        if !self.fork(id) {
+            return false;
+        }
+        let mut current = id;
+        while let Some(&child) = self.nodes[current.0].children.last() {
+            current = child;
+        }
+        self.active = Some(current);
+        true
+    }
+
+    /// Replace the message at `id` on a new branch, e.g. to edit an earlier
+    /// prompt, leaving the original and its replies intact. The new message
+    /// becomes the active node.
+    pub fn edit(&mut self, id: NodeId, message: Message) -> Option<NodeId> {
+        let parent = self.get(id)?.parent;
+        Some(self.attach(parent, message, None))
+    }
+
+    /// Add `reply` as an alternative to the active node, which must be an
+    /// assistant message, and make it the active node.
+    pub fn regenerate(&mut self, reply: Message) -> Option<NodeId> {
+        let active = self.active?;
+        if self.nodes[active.0].message.role != Role::Assistant {
+            return None;
+        }
+        self.edit(active, reply)
+    }
+
+    /// Remove every node added after the first `len`, e.g. to abandon a
+    /// failed attempt. If the active node is removed, none is active.
+    pub fn truncate(&mut self, len: usize) {
+        let kept = |id: &NodeId| id.0 < len;
+        self.nodes.truncate(len);
+        self.roots.retain(kept);
+        for node in &mut self.nodes {
+            node.children.retain(kept);
+        }
+        self.active = self.active.filter(kept);
+    }
+
+    fn attach(
+        &mut self,
+        parent: Option<NodeId>,
+        message: Message,
+        metadata: Option<Metadata>,
+    ) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            message,
+            metadata,
+            parent,
+            children: Vec::new(),
+        });
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        self.active = Some(id);
+        id
+    }
+}
+
+/// Serialized form of a `ConversationTree`: each node records only its
+/// parent, which must precede it.
+#[derive(Serialize, Deserialize)]
+struct TreeFile {
+    nodes: Vec<NodeFile>,
+    active: Option<NodeId>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NodeFile {
+    #[serde(flatten)]
+    message: Message,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<Metadata>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<NodeId>,
+}
+
+impl From<ConversationTree> for TreeFile {
+    fn from(tree: ConversationTree) -> Self {
+        let nodes = tree
+            .nodes
+            .into_iter()
+            .map(|node| NodeFile {
+                message: node.message,
+                metadata: node.metadata,
+                parent: node.parent,
+            })
+            .collect();
+        Self {
+            nodes,
+            active: tree.active,
+        }
+    }
+}
+
+impl TryFrom<TreeFile> for ConversationTree {
+    type Error = String;
+
+    fn try_from(file: TreeFile) -> Result<Self, Self::Error> {
+        let mut tree = ConversationTree::new();
+        for (i, node) in file.nodes.into_iter().enumerate() {
+            if node.parent.is_some_and(|parent| parent.0 >= i) {
+                return Err(format!("node {i} does not follow its parent"));
+            }
+            tree.attach(node.parent, node.message, node.metadata);
+        }
+        if file.active.is_some_and(|id| id.0 >= tree.len()) {
+            return Err("the active node does not exist".to_string());
+        }
+        tree.active = file.active;
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A question with two answers, the second active.
+    fn regenerated() -> (ConversationTree, [NodeId; 3]) {
+        let mut tree = ConversationTree::new();
+        let question = tree.push(Message::user("Name a colour"));
+        let first = tree.push(Message::assistant("Red"));
+        let second = tree.regenerate(Message::assistant("Blue")).unwrap();
+        (tree, [question, first, second])
+    }
+
+    #[test]
+    fn test_tree_regenerate_adds_sibling() {
+        let (tree, [question, first, second]) = regenerated();
+        assert_eq!(tree.siblings(second), vec![first, second]);
+        assert_eq!(tree.get(question).unwrap().children(), &[first, second]);
+        assert_eq!(tree.path(), vec![question, second]);
+        assert_eq!(
+            tree.messages(),
+            vec![Message::user("Name a colour"), Message::assistant("Blue")]
+        );
+
+        let mut tree = ConversationTree::from_messages([Message::user("Hi")]);
+        assert_eq!(tree.regenerate(Message::assistant("Hello")), None);
+    }
+
+    #[test]
+    fn test_tree_fork_and_switch() {
+        let (mut tree, [question, first, second]) = regenerated();
+        assert!(tree.switch(first));
+        let follow_up = tree.push(Message::user("Why red?"));
+        assert_eq!(tree.path(), vec![question, first, follow_up]);
+
+        // switching from the root follows the most recent replies
+        assert!(tree.switch(question));
+        assert_eq!(tree.active(), Some(second));
+
+        assert!(tree.fork(question));
+        assert_eq!(tree.messages(), vec![Message::user("Name a colour")]);
+        assert!(!tree.fork(NodeId(42)));
+
+        tree.reset();
+        let other = tree.push(Message::user("Name a shape"));
+        assert_eq!(tree.siblings(other), vec![question, other]);
+    }
+
+    #[test]
+    fn test_tree_edit_keeps_original() {
+        let (mut tree, [question, ..]) = regenerated();
+        let edited = tree.edit(question, Message::user("Name a shape")).unwrap();
+        assert_eq!(tree.siblings(edited), vec![question, edited]);
+        assert_eq!(tree.messages(), vec![Message::user("Name a shape")]);
+        assert_eq!(tree.len(), 4);
+    }
+
+    #[test]
+    fn test_tree_truncate() {
+        let (mut tree, [question, first, _]) = regenerated();
+        tree.truncate(2);
+        assert_eq!(tree.siblings(first), vec![first]);
+        assert_eq!(tree.active(), None);
+
+        tree.fork(first);
+        tree.truncate(1);
+        assert_eq!(tree.get(question).unwrap().children(), &[]);
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn test_tree_serde_round_trip() {
+        let (mut tree, [_, first, _]) = regenerated();
+        tree.switch(first);
+        tree.push_entry(Entry {
+            message: Message::user("Why red?"),
+            metadata: Some(Metadata::now()),
+        });
+        let json = serde_json::to_value(&tree).unwrap();
+        assert_eq!(json["active"], 3);
+        assert!(json["nodes"][3]["metadata"]["timestamp"].is_string());
+        assert_eq!(json["nodes"][2]["parent"], 0);
+        assert_eq!(
+            serde_json::from_value::<ConversationTree>(json).unwrap(),
+            tree
+        );
+
+        let cyclic = serde_json::json!({
+            "nodes": [{"role": "user", "content": "Hi", "parent": 0}],
+            "active": 0
+        });
+        assert!(serde_json::from_value::<ConversationTree>(cyclic).is_err());
+    }
+}